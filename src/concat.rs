@@ -0,0 +1,135 @@
+use std::fs::File;
+
+use super::{errors, utils::{RecordReader, RecordWriter}, Record, Result};
+
+/// How [CSV::concat](crate::CSV::concat) deals with headers that differ.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderPolicy {
+    /// Every header must be the same as the first, columns in the same order.
+    Strict,
+    /// Every column of every header, in the order they first appear. Records
+    /// without a column get an empty field.
+    Union,
+    /// Only the columns of the first header that every header has.
+    Intersection,
+}
+
+/// The header of the concatenated records. `sources` names each header for
+/// errors.
+pub fn combined_header(headers: &[&Record], sources: &[&str], policy: HeaderPolicy) -> Result<Record> {
+    let Some(first) = headers.first() else {
+        return Err(
+            Box::new(errors::DataNotFound {
+                message: String::from("Nothing to concatenate"),
+            })
+        );
+    };
+
+    let header = match policy {
+        HeaderPolicy::Strict => {
+            if let Some(i) = headers.iter().position(|header| header != first) {
+                return Err(
+                    Box::new(errors::HeaderMismatch {
+                        message: format!("Header of {} does not match the header of {}", sources[i], sources[0]),
+                    })
+                );
+            }
+            first.to_vec()
+        }
+        HeaderPolicy::Union => {
+            let mut header: Record = Vec::new();
+            for name in headers.iter().flat_map(|header| header.iter()) {
+                if !header.contains(name) {
+                    header.push(name.clone());
+                }
+            }
+            header
+        }
+        HeaderPolicy::Intersection => first
+            .iter()
+            .filter(|name| headers.iter().all(|header| header.contains(name)))
+            .cloned()
+            .collect(),
+    };
+
+    Ok(header)
+}
+
+/// For every column of `combined`, where to find it in `header`.
+pub fn column_map(header: &Record, combined: &Record) -> Vec<Option<usize>> {
+    combined.iter().map(|name| header.iter().position(|h| h == name)).collect()
+}
+
+/// `record` laid out in the combined columns.
+pub fn align(record: &Record, columns: &[Option<usize>]) -> Record {
+    columns
+        .iter()
+        .map(|column| column.and_then(|i| record.get(i)).cloned().unwrap_or_default())
+        .collect()
+}
+
+/// Concatenate the CSV files at `inputs` into `output`, one record at a time,
+/// so none of them has to fit in memory. Headers are combined by `policy` the
+/// same way as [CSV::concat](crate::CSV::concat), which takes a first pass
+/// reading only the header of each file. `output` is only replaced once
+/// every input is written, so it may be one of them. Returns the number of
+/// records written, the header excluded.
+pub fn concat_files(inputs: &[&str], output: &str, policy: HeaderPolicy) -> Result<usize> {
+    if inputs.iter().chain([&output]).any(|path| !path.ends_with(".csv")) {
+        return Err(
+            Box::new(errors::IncorrectFileType {
+                message: String::from("You must use \".csv\" file!")
+            })
+        );
+    }
+
+    let mut headers = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let header = RecordReader::new(File::open(input)?).next_record()?.ok_or_else(|| -> Box<dyn std::error::Error> {
+            Box::new(errors::DataNotFound {
+                message: format!("{} has no header", input),
+            })
+        })?;
+        headers.push(header);
+    }
+    let combined = combined_header(&headers.iter().collect::<Vec<_>>(), inputs, policy)?;
+
+    let mut writer = RecordWriter::new(output)?;
+    writer.write(&combined)?;
+    for (input, header) in inputs.iter().zip(&headers) {
+        let columns = column_map(header, &combined);
+        let mut reader = RecordReader::new(File::open(input)?);
+        reader.next_record()?;
+        while let Some(record) = reader.next_record()? {
+            writer.write(&align(&record, &columns))?;
+        }
+    }
+
+    Ok(writer.finish()? - 1)
+}
+
+
+#[cfg(test)]
+mod concat_tests {
+    use super::*;
+
+    fn record(fields: &[&str]) -> Record {
+        fields.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn header_policies() {
+        let a = record(&["id", "name", "city"]);
+        let b = record(&["city", "id", "email"]);
+        let sources = ["a.csv", "b.csv"];
+
+        assert_eq!(a, combined_header(&[&a, &a], &sources, HeaderPolicy::Strict).unwrap());
+        assert!(combined_header(&[&a, &b], &sources, HeaderPolicy::Strict).unwrap_err().is::<errors::HeaderMismatch>());
+        assert_eq!(record(&["id", "name", "city", "email"]), combined_header(&[&a, &b], &sources, HeaderPolicy::Union).unwrap());
+        assert_eq!(record(&["id", "city"]), combined_header(&[&a, &b], &sources, HeaderPolicy::Intersection).unwrap());
+        assert!(combined_header(&[], &[], HeaderPolicy::Union).is_err());
+
+        let columns = column_map(&b, &record(&["id", "name", "city", "email"]));
+        assert_eq!(record(&["1", "", "Oslo", "a@x"]), align(&record(&["Oslo", "1", "a@x"]), &columns));
+    }
+}
@@ -0,0 +1,176 @@
+use std::{collections::{HashMap, HashSet}, fs::File};
+
+use super::{errors, utils::{self, RecordReader, RecordWriter}, Record, Result};
+
+/// Which record of a set of duplicates [CSV::dedup](crate::CSV::dedup) keeps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Keep {
+    First,
+    Last,
+    /// Drop every record that has a duplicate.
+    None,
+}
+
+/// Columns that make up the key of a record, `None` for the whole record.
+pub fn key_columns(header: &Record, keys: Option<&[&str]>) -> Result<Option<Vec<usize>>> {
+    keys.map(|names| {
+        names
+            .iter()
+            .map(|name| utils::column_index(std::slice::from_ref(header), name))
+            .collect()
+    })
+    .transpose()
+}
+
+fn key(record: &Record, columns: &Option<Vec<usize>>) -> Vec<String> {
+    match columns {
+        Some(columns) => columns.iter().map(|i| record.get(*i).cloned().unwrap_or_default()).collect(),
+        None => record.clone(),
+    }
+}
+
+/// Number of times each key occurs in `records`.
+fn count_keys<'r, I>(records: I, columns: &Option<Vec<usize>>) -> HashMap<Vec<String>, usize>
+where
+    I: IntoIterator<Item = &'r Record>,
+{
+    let mut counts = HashMap::new();
+    for record in records {
+        *counts.entry(key(record, columns)).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Decides record by record, in order, whether it is kept. `counts` is only
+/// needed for [`Keep::Last`] and [`Keep::None`], which have to know how often
+/// a key still comes up.
+struct Deduper {
+    keep: Keep,
+    columns: Option<Vec<usize>>,
+    seen: HashSet<Vec<String>>,
+    counts: HashMap<Vec<String>, usize>,
+}
+
+impl Deduper {
+    fn keeps(&mut self, record: &Record) -> bool {
+        let key = key(record, &self.columns);
+        match self.keep {
+            Keep::First => self.seen.insert(key),
+            Keep::Last => {
+                let left = self.counts.get_mut(&key).expect("record was not counted");
+                *left -= 1;
+                *left == 0
+            }
+            Keep::None => self.counts.get(&key) == Some(&1),
+        }
+    }
+}
+
+/// For every record after the header, whether [CSV::dedup](crate::CSV::dedup)
+/// keeps it.
+pub fn kept(data: &[Record], columns: Option<Vec<usize>>, keep: Keep) -> Vec<bool> {
+    let counts = match keep {
+        Keep::First => HashMap::new(),
+        _ => count_keys(&data[1..], &columns),
+    };
+    let mut deduper = Deduper { keep, columns, seen: HashSet::new(), counts };
+    data[1..].iter().map(|record| deduper.keeps(record)).collect()
+}
+
+/// Indices in `data` of every record whose key occurs more than once.
+pub fn duplicates(data: &[Record], columns: Option<Vec<usize>>) -> Vec<usize> {
+    let counts = count_keys(&data[1..], &columns);
+    data.iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, record)| counts[&key(record, &columns)] > 1)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Remove duplicate records from the CSV file at `input` and write the rest,
+/// in order, to `output`, without loading the file. Only the keys are held in
+/// memory; [`Keep::Last`] and [`Keep::None`] read the file twice, once to
+/// count them. The header is always kept and `output` is only replaced once
+/// it is complete, so it may be `input`. Returns the number of records
+/// dropped.
+pub fn dedup_file(input: &str, output: &str, keys: Option<&[&str]>, keep: Keep) -> Result<usize> {
+    if !input.ends_with(".csv") || !output.ends_with(".csv") {
+        return Err(
+            Box::new(errors::IncorrectFileType {
+                message: String::from("You must use \".csv\" file!")
+            })
+        );
+    }
+
+    let mut reader = RecordReader::new(File::open(input)?);
+    let header = match reader.next_record()? {
+        Some(header) => header,
+        None => {
+            return Err(
+                Box::new(errors::DataNotFound {
+                    message: format!("{} has no header", input),
+                })
+            );
+        }
+    };
+    let columns = key_columns(&header, keys)?;
+
+    let mut counts = HashMap::new();
+    if keep != Keep::First {
+        while let Some(record) = reader.next_record()? {
+            *counts.entry(key(&record, &columns)).or_insert(0) += 1;
+        }
+        reader = RecordReader::new(File::open(input)?);
+        reader.next_record()?;
+    }
+
+    let mut deduper = Deduper { keep, columns, seen: HashSet::new(), counts };
+    let mut writer = RecordWriter::new(output)?;
+    writer.write(&header)?;
+    let mut dropped = 0;
+    while let Some(record) = reader.next_record()? {
+        if deduper.keeps(&record) {
+            writer.write(&record)?;
+        } else {
+            dropped += 1;
+        }
+    }
+    writer.finish()?;
+
+    Ok(dropped)
+}
+
+
+#[cfg(test)]
+mod dedup_tests {
+    use super::*;
+
+    fn data() -> Vec<Record> {
+        let rows: &[&[&str]] = &[
+            &["id", "name"],
+            &["1", "ann"],
+            &["2", "bob"],
+            &["1", "ann"],
+            &["3", "bob"],
+            &["1", "ann"],
+        ];
+        rows.iter().map(|r| r.iter().map(|f| f.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn keeps_the_right_records() {
+        let by_name = key_columns(&data()[0], Some(&["name"])).unwrap();
+
+        assert_eq!(vec![true, true, false, true, false], kept(&data(), None, Keep::First));
+        assert_eq!(vec![false, true, false, true, true], kept(&data(), None, Keep::Last));
+        assert_eq!(vec![false, true, false, true, false], kept(&data(), None, Keep::None));
+        assert_eq!(vec![true, true, false, false, false], kept(&data(), by_name.clone(), Keep::First));
+        assert_eq!(vec![false, false, false, false, false], kept(&data(), by_name.clone(), Keep::None));
+
+        assert_eq!(vec![1, 3, 5], duplicates(&data(), None));
+        assert_eq!(vec![1, 2, 3, 4, 5], duplicates(&data(), by_name));
+
+        assert!(key_columns(&data()[0], Some(&["missing"])).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::{errors, utils, Record, Result, CSV};
+
+/// One field that differs between the old and new version of a record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellChange {
+    pub column: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// A record found in both CSVs whose fields differ.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowChange {
+    /// Values of the key columns.
+    pub key: Vec<String>,
+    pub old: Record,
+    pub new: Record,
+    pub changes: Vec<CellChange>,
+}
+
+/// The differences between two CSVs, see [`diff`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diff {
+    pub old_path: String,
+    pub new_path: String,
+    pub old_header: Record,
+    pub new_header: Record,
+    pub key_columns: Vec<String>,
+    /// Columns only in the new header, in its order.
+    pub added_columns: Vec<String>,
+    /// Columns only in the old header, in its order.
+    pub removed_columns: Vec<String>,
+    /// Records only in the new CSV, in its order.
+    pub added: Vec<Record>,
+    /// Records only in the old CSV, in its order.
+    pub removed: Vec<Record>,
+    /// Records in both with different fields, in the order of the new CSV.
+    pub modified: Vec<RowChange>,
+}
+
+/// Compare `old` with `new`. Records are matched by the values of
+/// `key_columns`, which both headers must have; when a key occurs more than
+/// once the occurrences are matched in order. Without key columns records
+/// are matched on every column the headers share, so only additions and
+/// removals are found. Fields are compared by column name over the columns
+/// in both headers, and fields missing from ragged records count as empty.
+pub fn diff(old: &CSV, new: &CSV, key_columns: &[&str]) -> Result<Diff> {
+    let (old_header, new_header) = match (old.data.first(), new.data.first()) {
+        (Some(old_header), Some(new_header)) => (old_header, new_header),
+        _ => {
+            return Err(
+                Box::new(errors::DataNotFound {
+                    message: String::from("Both CSVs need a header to diff"),
+                })
+            );
+        }
+    };
+
+    // (old, new) positions of every column in both headers, in new order.
+    let shared: Vec<(usize, usize)> = new_header
+        .iter()
+        .enumerate()
+        .filter_map(|(n, name)| old_header.iter().position(|o| o == name).map(|o| (o, n)))
+        .collect();
+
+    let keys: Vec<(usize, usize)> = if key_columns.is_empty() {
+        shared.clone()
+    } else {
+        key_columns
+            .iter()
+            .map(|name| {
+                Ok((utils::column_index(&old.data, name)?, utils::column_index(&new.data, name)?))
+            })
+            .collect::<Result<_>>()?
+    };
+
+    let field = |record: &Record, i: usize| record.get(i).cloned().unwrap_or_default();
+
+    let mut old_rows: HashMap<Vec<String>, VecDeque<usize>> = HashMap::new();
+    for (i, record) in old.data.iter().enumerate().skip(1) {
+        let key = keys.iter().map(|(o, _)| field(record, *o)).collect();
+        old_rows.entry(key).or_default().push_back(i);
+    }
+
+    let mut matched = vec![false; old.data.len()];
+    let mut added = Vec::new();
+    let mut modified = Vec::new();
+    for record in new.data.iter().skip(1) {
+        let key: Vec<String> = keys.iter().map(|(_, n)| field(record, *n)).collect();
+        let Some(row) = old_rows.get_mut(&key).and_then(|rows| rows.pop_front()) else {
+            added.push(record.clone());
+            continue;
+        };
+        matched[row] = true;
+
+        let old_record = &old.data[row];
+        let changes: Vec<CellChange> = shared
+            .iter()
+            .filter(|(o, n)| field(old_record, *o) != field(record, *n))
+            .map(|(o, n)| CellChange {
+                column: new_header[*n].clone(),
+                old: field(old_record, *o),
+                new: field(record, *n),
+            })
+            .collect();
+        if !changes.is_empty() {
+            modified.push(RowChange { key, old: old_record.clone(), new: record.clone(), changes });
+        }
+    }
+
+    let removed = old.data
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(i, _)| !matched[*i])
+        .map(|(_, record)| record.clone())
+        .collect();
+
+    Ok(Diff {
+        old_path: old.path.to_string(),
+        new_path: new.path.to_string(),
+        old_header: old_header.clone(),
+        new_header: new_header.clone(),
+        key_columns: keys.iter().map(|(_, n)| new_header[*n].clone()).collect(),
+        added_columns: new_header.iter().filter(|name| !old_header.contains(name)).cloned().collect(),
+        removed_columns: old_header.iter().filter(|name| !new_header.contains(name)).cloned().collect(),
+        added,
+        removed,
+        modified,
+    })
+}
+
+impl Diff {
+    /// Checks whether the two CSVs had no differences at all.
+    pub fn is_empty(&self) -> bool {
+        self.added_columns.is_empty()
+            && self.removed_columns.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+    }
+
+    /// The changed records as a CSV at `path`. The first column, `change`,
+    /// is `added`, `removed`, or `modified_old` and `modified_new` for the two
+    /// versions of a modified record. The other columns are the new header
+    /// followed by the removed columns, so every record is shown whole.
+    pub fn to_csv<'p>(&self, path: &'p str) -> CSV<'p> {
+        let mut header = vec![String::from("change")];
+        header.extend(self.new_header.iter().cloned());
+        header.extend(self.removed_columns.iter().cloned());
+
+        // Lays a record out in the diff's columns, by name.
+        let layout = |change: &str, record: &Record, from: &Record| -> Record {
+            let mut line = vec![change.to_string()];
+            line.extend(header[1..].iter().map(|name| {
+                from.iter()
+                    .position(|h| h == name)
+                    .and_then(|i| record.get(i))
+                    .cloned()
+                    .unwrap_or_default()
+            }));
+            line
+        };
+
+        let mut data = Vec::with_capacity(1 + self.added.len() + self.removed.len() + 2 * self.modified.len());
+        for change in &self.modified {
+            data.push(layout("modified_old", &change.old, &self.old_header));
+            data.push(layout("modified_new", &change.new, &self.new_header));
+        }
+        data.extend(self.removed.iter().map(|record| layout("removed", record, &self.old_header)));
+        data.extend(self.added.iter().map(|record| layout("added", record, &self.new_header)));
+        data.insert(0, header);
+
+        CSV::new_with_data(path, data)
+    }
+
+    /// A report in the style of a unified diff: `-` for what is only in the
+    /// old CSV and `+` for what is only in the new one, with a `@@` line
+    /// naming the key of each modified record followed by its changed fields.
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![format!("--- {}", self.old_path), format!("+++ {}", self.new_path)];
+
+        lines.extend(self.removed_columns.iter().map(|name| format!("-column {}", name)));
+        lines.extend(self.added_columns.iter().map(|name| format!("+column {}", name)));
+
+        for change in &self.modified {
+            let key: Vec<String> = self.key_columns
+                .iter()
+                .zip(&change.key)
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            lines.push(format!("@@ {} @@", key.join(", ")));
+            for cell in &change.changes {
+                lines.push(format!("-{}: {}", cell.column, cell.old));
+                lines.push(format!("+{}: {}", cell.column, cell.new));
+            }
+        }
+
+        lines.extend(self.removed.iter().map(|record| format!("-{}", utils::record_to_quoted_line(record, ','))));
+        lines.extend(self.added.iter().map(|record| format!("+{}", utils::record_to_quoted_line(record, ','))));
+
+        lines.join("\n")
+    }
+}
+
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+
+    fn csv<'p>(path: &'p str, rows: &[&[&str]]) -> CSV<'p> {
+        CSV::new_with_data(path, rows.iter().map(|r| r.iter().map(|f| f.to_string()).collect()).collect())
+    }
+
+    #[test]
+    fn rows_and_columns() {
+        let old = csv("old.csv", &[&["id", "name", "fax"], &["1", "ann", "x"], &["2", "bob", "y"], &["4", "dee", ""]]);
+        let new = csv("new.csv", &[&["id", "name", "email"], &["2", "rob", "r@x"], &["1", "ann", "a@x"], &["3", "cy", ""]]);
+
+        let diff = diff(&old, &new, &["id"]).unwrap();
+        assert_eq!(vec!["email"], diff.added_columns);
+        assert_eq!(vec!["fax"], diff.removed_columns);
+        assert_eq!(vec![vec!["3", "cy", ""]], diff.added);
+        assert_eq!(vec![vec!["4", "dee", ""]], diff.removed);
+        assert_eq!(1, diff.modified.len());
+        assert_eq!(vec!["2"], diff.modified[0].key);
+        assert_eq!(
+            vec![CellChange { column: String::from("name"), old: String::from("bob"), new: String::from("rob") }],
+            diff.modified[0].changes
+        );
+
+        assert_eq!(
+            "--- old.csv\n+++ new.csv\n-column fax\n+column email\n@@ id=2 @@\n-name: bob\n+name: rob\n-4,dee,\n+3,cy,",
+            diff.to_text()
+        );
+
+        let report = diff.to_csv("diff.csv");
+        assert_eq!(vec!["change", "id", "name", "email", "fax"], report.data[0]);
+        assert_eq!(vec!["modified_old", "2", "bob", "", "y"], report.data[1]);
+        assert_eq!(vec!["modified_new", "2", "rob", "r@x", ""], report.data[2]);
+        assert_eq!(vec!["removed", "4", "dee", "", ""], report.data[3]);
+        assert_eq!(vec!["added", "3", "cy", "", ""], report.data[4]);
+
+        assert!(super::diff(&old, &new, &["fax"]).is_err());
+    }
+
+    #[test]
+    fn without_keys_and_duplicates() {
+        let old = csv("old.csv", &[&["a", "b"], &["1", "x"], &["1", "x"], &["2", "y"]]);
+        let new = csv("new.csv", &[&["a", "b"], &["1", "x"], &["2", "z"]]);
+
+        let diff = diff(&old, &new, &[]).unwrap();
+        assert_eq!(vec![vec!["2", "z"]], diff.added);
+        assert_eq!(vec![vec!["1", "x"], vec!["2", "y"]], diff.removed);
+        assert!(diff.modified.is_empty());
+
+        assert!(super::diff(&old, &old, &["a"]).unwrap().is_empty());
+    }
+}
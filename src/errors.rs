@@ -69,4 +69,172 @@ impl std::error::Error for ParseError {
     fn cause(&self) -> Option<&dyn std::error::Error> {
         None
     }
-}
\ No newline at end of file
+}
+
+
+#[derive(Debug, Clone)]
+pub struct StaleIndex {
+    pub message: String,
+}
+
+impl fmt::Display for StaleIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Index is stale, the CSV file changed after it was built")
+    }
+}
+
+impl std::error::Error for StaleIndex {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+    fn description(&self) -> &str {
+        &self.message
+    }
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        None
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct HeaderMismatch {
+    pub message: String,
+}
+
+impl fmt::Display for HeaderMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Headers do not match")
+    }
+}
+
+impl std::error::Error for HeaderMismatch {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+    fn description(&self) -> &str {
+        &self.message
+    }
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        None
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct UnsavedChanges {
+    pub message: String,
+}
+
+impl fmt::Display for UnsavedChanges {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CSV has unsaved changes")
+    }
+}
+
+impl std::error::Error for UnsavedChanges {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+    fn description(&self) -> &str {
+        &self.message
+    }
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        None
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct FileChanged {
+    pub message: String,
+}
+
+impl fmt::Display for FileChanged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "File changed on disk since it was read")
+    }
+}
+
+impl std::error::Error for FileChanged {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+    fn description(&self) -> &str {
+        &self.message
+    }
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        None
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct FileLocked {
+    pub message: String,
+}
+
+impl fmt::Display for FileLocked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "File is locked by another process")
+    }
+}
+
+impl std::error::Error for FileLocked {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+    fn description(&self) -> &str {
+        &self.message
+    }
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        None
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct ColumnNotFound {
+    pub message: String,
+}
+
+impl fmt::Display for ColumnNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Column not found in the header")
+    }
+}
+
+impl std::error::Error for ColumnNotFound {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+    fn description(&self) -> &str {
+        &self.message
+    }
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        None
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct DuplicateKey {
+    pub message: String,
+}
+
+impl fmt::Display for DuplicateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Duplicate key")
+    }
+}
+
+impl std::error::Error for DuplicateKey {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+    fn description(&self) -> &str {
+        &self.message
+    }
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        None
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    mem,
+    path::{Path, PathBuf},
+};
+
+use super::{errors, sort, utils::{self, RecordReader, RecordWriter}, Record, Result, SortKey};
+
+/// Rough number of bytes a record takes up in memory.
+fn record_size(record: &Record) -> usize {
+    mem::size_of::<Record>()
+        + record.iter().map(|field| mem::size_of::<String>() + field.capacity()).sum::<usize>()
+}
+
+/// Temporary run files, removed again when dropped, also when sorting fails.
+struct Runs {
+    paths: Vec<PathBuf>,
+}
+
+impl Drop for Runs {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            fs::remove_file(path).unwrap_or(());
+        }
+    }
+}
+
+impl Runs {
+    /// Create a new run file next to `output` and write it with `write`.
+    /// Returns the path of the run.
+    fn write<F>(&mut self, output: &Path, write: F) -> Result<PathBuf>
+    where
+        F: FnOnce(&mut RunWriter) -> Result<()>,
+    {
+        let (path, file) = utils::create_temp_file(output)?;
+        self.paths.push(path.clone());
+        let mut writer = RunWriter { writer: BufWriter::new(file) };
+        write(&mut writer)?;
+        writer.writer.into_inner().map_err(|e| e.into_error())?;
+        Ok(path)
+    }
+}
+
+/// Writes a run file. Runs end every record with `\n`, so a last record
+/// holding a single empty field still reads back. The output leaves the last
+/// `\n` off, the same as [CSV::save](crate::CSV::save).
+struct RunWriter {
+    writer: BufWriter<File>,
+}
+
+impl RunWriter {
+    fn write(&mut self, record: &Record) -> Result<()> {
+        self.writer.write_all(utils::record_to_quoted_line(record, ',').as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// Sort the CSV file at `input` by `keys` into `output`, for files too large
+/// to load with [CSV::new_from_file](crate::CSV::new_from_file).
+///
+/// Records are read in runs of roughly `memory_budget` bytes. Each run is
+/// sorted and, if the file doesn't fit in a single run, spilled to a
+/// temporary CSV file next to `output`. The runs are then merged into
+/// `output`. The header is kept as the first record, the sort is stable and
+/// `input` and `output` may be the same file, it is only replaced once the
+/// sorted file is complete. Returns the number of runs used.
+///
+/// Fields holding a `,` or a newline are written surrounded by quotes, so
+/// they read back as they were.
+pub fn external_sort(input: &str, output: &str, keys: &[SortKey], memory_budget: usize) -> Result<usize> {
+    if !input.ends_with(".csv") || !output.ends_with(".csv") {
+        return Err(
+            Box::new(errors::IncorrectFileType {
+                message: String::from("You must use \".csv\" file!")
+            })
+        );
+    }
+
+    let mut reader = RecordReader::new(File::open(input)?);
+    let header = match reader.next_record()? {
+        Some(header) => header,
+        None => {
+            return Err(
+                Box::new(errors::DataNotFound {
+                    message: format!("{} has no header", input),
+                })
+            );
+        }
+    };
+    let keys = sort::resolve_keys(std::slice::from_ref(&header), keys)?;
+
+    let mut runs = Runs { paths: Vec::new() };
+    let mut run: Vec<Record> = Vec::new();
+    let mut run_size = 0;
+    let mut last_run = false;
+
+    while !last_run {
+        match reader.next_record()? {
+            Some(record) => {
+                run_size += record_size(&record);
+                run.push(record);
+                if run_size < memory_budget {
+                    continue;
+                }
+            }
+            None => last_run = true,
+        }
+
+        let order = sort::sorted_order(&run, &keys);
+
+        // Everything fit in one run, no need to spill or merge.
+        if last_run && runs.paths.is_empty() {
+            let mut writer = RecordWriter::new(output)?;
+            writer.write(&header)?;
+            for i in &order {
+                writer.write(&run[*i])?;
+            }
+            writer.finish()?;
+            return Ok(1);
+        }
+
+        if !run.is_empty() {
+            runs.write(Path::new(output), |writer| {
+                order.iter().try_for_each(|i| writer.write(&run[*i]))
+            })?;
+        }
+        run.clear();
+        run_size = 0;
+    }
+
+    merge_runs(&runs.paths, &header, &keys, output)?;
+    Ok(runs.paths.len())
+}
+
+/// Most run files merged at once. With more runs they are merged in passes,
+/// so only this many files are ever open.
+const MERGE_FAN_IN: usize = 64;
+
+/// The next record of a run, ordered so the [`BinaryHeap`] yields the
+/// smallest key first and, on equal keys, the earlier run.
+struct Head<'k> {
+    values: Vec<sort::SortValue>,
+    run: usize,
+    keys: &'k [sort::ResolvedKey],
+}
+
+impl Ord for Head<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        sort::compare_record_values(&self.values, &other.values, self.keys)
+            .then(self.run.cmp(&other.run))
+            .reverse()
+    }
+}
+
+impl PartialOrd for Head<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head<'_> {}
+
+/// Merge sorted run files into `output`, at most [`MERGE_FAN_IN`] at a time.
+/// Runs are merged in groups of neighbours into new runs until few enough
+/// are left for a last merge that writes the header and the records. On
+/// equal keys the record from the earlier run goes first, which keeps the
+/// sort stable.
+fn merge_runs(paths: &[PathBuf], header: &Record, keys: &[sort::ResolvedKey], output: &str) -> Result<()> {
+    let mut runs = Runs { paths: Vec::new() };
+    let mut pending: Vec<PathBuf> = paths.to_vec();
+
+    while pending.len() > MERGE_FAN_IN {
+        let mut merged = Vec::with_capacity(pending.len().div_ceil(MERGE_FAN_IN));
+        for group in pending.chunks(MERGE_FAN_IN) {
+            merged.push(runs.write(Path::new(output), |writer| merge_group(group, keys, |record| writer.write(record)))?);
+        }
+        pending = merged;
+    }
+
+    let mut writer = RecordWriter::new(output)?;
+    writer.write(header)?;
+    merge_group(&pending, keys, |record| writer.write(record))?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// K-way merge of the run files at `paths`, handing every record to `emit`
+/// in sorted order.
+fn merge_group<F>(paths: &[PathBuf], keys: &[sort::ResolvedKey], mut emit: F) -> Result<()>
+where
+    F: FnMut(&Record) -> Result<()>,
+{
+    let mut readers = paths
+        .iter()
+        .map(|path| Ok(RecordReader::new(File::open(path)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut records: Vec<Option<Record>> = Vec::with_capacity(readers.len());
+    let mut heap = BinaryHeap::with_capacity(readers.len());
+    for (run, reader) in readers.iter_mut().enumerate() {
+        let record = reader.next_record()?;
+        if let Some(record) = &record {
+            heap.push(Head { values: sort::record_values(record, keys), run, keys });
+        }
+        records.push(record);
+    }
+
+    while let Some(Head { run, .. }) = heap.pop() {
+        let record = records[run].take().expect("run in the heap without a record");
+        emit(&record)?;
+        records[run] = readers[run].next_record()?;
+        if let Some(record) = &records[run] {
+            heap.push(Head { values: sort::record_values(record, keys), run, keys });
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,323 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{utils, Record, Result, CSV};
+
+/// One aggregation for [GroupBy::agg]. The result column is named after the
+/// source column and the aggregation, e.g. `revenue_sum`, unless renamed
+/// with [`Agg::alias`].
+///
+/// `sum`, `mean`, `min` and `max` read fields as numbers. Empty fields are
+/// skipped, fields that aren't numbers are skipped too and reported in
+/// [`Aggregated::failures`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Agg {
+    kind: Aggregate,
+    column: Option<String>,
+    name: Option<String>,
+}
+
+/// What an [`Agg`] or [CSV::pivot](crate::CSV::pivot) computes, see the
+/// constructors of [`Agg`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregate {
+    Count,
+    Sum,
+    Mean,
+    Min,
+    Max,
+    First,
+    DistinctCount,
+}
+
+impl Agg {
+    fn on(kind: Aggregate, column: &str) -> Agg {
+        Agg { kind, column: Some(column.to_string()), name: None }
+    }
+
+    /// Number of records in the group.
+    pub fn count() -> Agg {
+        Agg { kind: Aggregate::Count, column: None, name: None }
+    }
+
+    /// Total of the numbers in `column`, `0` for none.
+    pub fn sum(column: &str) -> Agg {
+        Agg::on(Aggregate::Sum, column)
+    }
+
+    /// Average of the numbers in `column`, empty for none.
+    pub fn mean(column: &str) -> Agg {
+        Agg::on(Aggregate::Mean, column)
+    }
+
+    /// Smallest number in `column`, empty for none.
+    pub fn min(column: &str) -> Agg {
+        Agg::on(Aggregate::Min, column)
+    }
+
+    /// Largest number in `column`, empty for none.
+    pub fn max(column: &str) -> Agg {
+        Agg::on(Aggregate::Max, column)
+    }
+
+    /// Field of `column` in the first record of the group, as it is.
+    pub fn first(column: &str) -> Agg {
+        Agg::on(Aggregate::First, column)
+    }
+
+    /// Number of different non-empty values in `column`.
+    pub fn distinct_count(column: &str) -> Agg {
+        Agg::on(Aggregate::DistinctCount, column)
+    }
+
+    /// Name the result column `name`.
+    pub fn alias(mut self, name: &str) -> Agg {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    fn result_name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        let kind = match self.kind {
+            Aggregate::Count => "count",
+            Aggregate::Sum => "sum",
+            Aggregate::Mean => "mean",
+            Aggregate::Min => "min",
+            Aggregate::Max => "max",
+            Aggregate::First => "first",
+            Aggregate::DistinctCount => "distinct_count",
+        };
+        match &self.column {
+            Some(column) => format!("{}_{}", column, kind),
+            None => kind.to_string(),
+        }
+    }
+}
+
+/// Running value of one [`Agg`] for one group.
+#[derive(Debug)]
+pub(crate) enum State {
+    Count(usize),
+    Sum(f64),
+    Mean(f64, usize),
+    Min(Option<f64>),
+    Max(Option<f64>),
+    First(Option<String>),
+    Distinct(HashSet<String>),
+}
+
+impl State {
+    pub(crate) fn new(kind: Aggregate) -> State {
+        match kind {
+            Aggregate::Count => State::Count(0),
+            Aggregate::Sum => State::Sum(0.0),
+            Aggregate::Mean => State::Mean(0.0, 0),
+            Aggregate::Min => State::Min(None),
+            Aggregate::Max => State::Max(None),
+            Aggregate::First => State::First(None),
+            Aggregate::DistinctCount => State::Distinct(HashSet::new()),
+        }
+    }
+
+    /// Add one field. Returns `false` if the state wanted a number and
+    /// `field` isn't one, empty fields are skipped.
+    pub(crate) fn add(&mut self, field: &str) -> bool {
+        let number = match self {
+            State::Count(n) => {
+                *n += 1;
+                return true;
+            }
+            State::First(first) => {
+                first.get_or_insert_with(|| field.to_string());
+                return true;
+            }
+            State::Distinct(values) => {
+                if !field.trim().is_empty() {
+                    values.insert(field.to_string());
+                }
+                return true;
+            }
+            _ if field.trim().is_empty() => return true,
+            _ => match field.trim().parse::<f64>() {
+                Ok(number) if !number.is_nan() => number,
+                _ => return false,
+            },
+        };
+
+        match self {
+            State::Sum(sum) => *sum += number,
+            State::Mean(sum, n) => {
+                *sum += number;
+                *n += 1;
+            }
+            State::Min(min) => *min = Some(min.map_or(number, |m| m.min(number))),
+            State::Max(max) => *max = Some(max.map_or(number, |m| m.max(number))),
+            _ => {}
+        }
+        true
+    }
+
+    pub(crate) fn result(&self) -> String {
+        match self {
+            State::Count(n) => n.to_string(),
+            State::Sum(sum) => sum.to_string(),
+            State::Mean(_, 0) => String::new(),
+            State::Mean(sum, n) => (sum / *n as f64).to_string(),
+            State::Min(value) | State::Max(value) => value.map_or_else(String::new, |v| v.to_string()),
+            State::First(value) => value.clone().unwrap_or_default(),
+            State::Distinct(values) => values.len().to_string(),
+        }
+    }
+}
+
+/// A field [GroupBy::agg] skipped because it isn't a number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggFailure {
+    /// Index of the record in `CSV.data`.
+    pub row: usize,
+    pub column: String,
+    pub value: String,
+}
+
+/// Fields that weren't numbers, each reported once even when several
+/// aggregations read it.
+#[derive(Debug, Default)]
+pub(crate) struct Failures {
+    pub(crate) list: Vec<AggFailure>,
+    reported: HashSet<(usize, usize)>,
+}
+
+impl Failures {
+    pub(crate) fn report(&mut self, data: &[Record], row: usize, column: usize, value: &str) {
+        if self.reported.insert((row, column)) {
+            self.list.push(AggFailure {
+                row,
+                column: data[0][column].clone(),
+                value: value.to_string(),
+            });
+        }
+    }
+}
+
+/// The result of [GroupBy::agg] and [CSV::pivot](crate::CSV::pivot): the
+/// new CSV, and every field that was skipped because it isn't a number.
+#[derive(Debug)]
+pub struct Aggregated<'a> {
+    pub csv: CSV<'a>,
+    pub failures: Vec<AggFailure>,
+}
+
+/// Records grouped by the values of some columns, see
+/// [CSV::group_by](crate::CSV::group_by).
+#[derive(Debug)]
+pub struct GroupBy<'g, 'a> {
+    csv: &'g CSV<'a>,
+    columns: Vec<usize>,
+}
+
+impl<'g, 'a> GroupBy<'g, 'a> {
+    pub(crate) fn new(csv: &'g CSV<'a>, names: &[&str]) -> Result<GroupBy<'g, 'a>> {
+        let columns = names
+            .iter()
+            .map(|name| utils::column_index(&csv.data, name))
+            .collect::<Result<Vec<usize>>>()?;
+        Ok(GroupBy { csv, columns })
+    }
+
+    /// Run `aggs` over every group. The new CSV has the group columns followed
+    /// by one column per aggregation and a record per group, in the order the
+    /// groups first appear. It has the same path as the grouped CSV. Fails if
+    /// an aggregation names an unknown column.
+    pub fn agg(&self, aggs: &[Agg]) -> Result<Aggregated<'a>> {
+        let data = &self.csv.data;
+        let agg_columns = aggs
+            .iter()
+            .map(|agg| match &agg.column {
+                Some(name) => utils::column_index(data, name).map(Some),
+                None => Ok(None),
+            })
+            .collect::<Result<Vec<Option<usize>>>>()?;
+
+        let mut header: Record = self.columns.iter().map(|i| data[0][*i].clone()).collect();
+        header.extend(aggs.iter().map(Agg::result_name));
+
+        let mut groups: HashMap<Vec<String>, usize> = HashMap::new();
+        let mut keys: Vec<Vec<String>> = Vec::new();
+        let mut states: Vec<Vec<State>> = Vec::new();
+        let mut failures = Failures::default();
+
+        for (row, record) in data.iter().enumerate().skip(1) {
+            let key: Vec<String> = self.columns
+                .iter()
+                .map(|i| record.get(*i).cloned().unwrap_or_default())
+                .collect();
+            let group = *groups.entry(key.clone()).or_insert_with(|| {
+                keys.push(key);
+                states.push(aggs.iter().map(|agg| State::new(agg.kind)).collect());
+                states.len() - 1
+            });
+
+            for (i, state) in states[group].iter_mut().enumerate() {
+                let field = agg_columns[i].and_then(|column| record.get(column)).map_or("", |f| f.as_str());
+                if !state.add(field) {
+                    failures.report(data, row, agg_columns[i].unwrap_or_default(), field);
+                }
+            }
+        }
+
+        let mut grouped = vec![header];
+        for (key, states) in keys.into_iter().zip(states) {
+            let mut record = key;
+            record.extend(states.iter().map(State::result));
+            grouped.push(record);
+        }
+
+        Ok(Aggregated { csv: CSV::from_parts(self.csv.path, grouped, 0), failures: failures.list })
+    }
+}
+
+
+#[cfg(test)]
+mod group_tests {
+    use super::*;
+
+    #[test]
+    fn aggregations_per_group() {
+        let rows: &[&[&str]] = &[
+            &["region", "revenue", "price", "rep"],
+            &["north", "10", "2.5", "ann"],
+            &["south", "4", "1", "bob"],
+            &["north", "5.5", "n/a", "ann"],
+            &["north", "", "3.5", "cy"],
+            &["south", "x", "", ""],
+        ];
+        let data = rows.iter().map(|r| r.iter().map(|f| f.to_string()).collect()).collect();
+        let csv = CSV::new_with_data("sales.csv", data);
+
+        let result = csv.group_by(&["region"]).unwrap().agg(&[
+            Agg::sum("revenue"),
+            Agg::count(),
+            Agg::mean("price").alias("avg_price"),
+            Agg::min("revenue"),
+            Agg::max("price"),
+            Agg::first("rep"),
+            Agg::distinct_count("rep"),
+        ]).unwrap();
+
+        assert_eq!(
+            vec!["region", "revenue_sum", "count", "avg_price", "revenue_min", "price_max", "rep_first", "rep_distinct_count"],
+            result.csv.data[0]
+        );
+        assert_eq!(vec!["north", "15.5", "3", "3", "5.5", "3.5", "ann", "2"], result.csv.data[1]);
+        assert_eq!(vec!["south", "4", "2", "1", "4", "1", "bob", "1"], result.csv.data[2]);
+
+        assert_eq!(vec![
+            AggFailure { row: 3, column: String::from("price"), value: String::from("n/a") },
+            AggFailure { row: 5, column: String::from("revenue"), value: String::from("x") },
+        ], result.failures);
+
+        assert!(csv.group_by(&["missing"]).is_err());
+        assert!(csv.group_by(&["region"]).unwrap().agg(&[Agg::sum("missing")]).is_err());
+    }
+}
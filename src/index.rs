@@ -0,0 +1,333 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+    str,
+    time::UNIX_EPOCH,
+};
+
+use super::{errors, utils, Record, Result};
+
+const MAGIC: &[u8; 8] = b"CSVIDX01";
+
+/// Byte offset of every record start within a CSV file on disk, together with
+/// the size and modification time the file had when the index was built.
+///
+/// Offsets are found the same way [`CSV::new_from_file`](crate::CSV::new_from_file)
+/// splits lines, so newlines inside quoted fields do not start a new record and
+/// record `n` of the index is `CSV.data[n]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CSVIndex {
+    pub file_len: u64,
+    pub modified: u128,
+    offsets: Vec<u64>,
+}
+
+/// Sidecar path an index is saved to, e.g. `data.csv` -> `data.csv.idx`.
+pub fn index_path(csv_path: &str) -> String {
+    format!("{}.idx", csv_path)
+}
+
+/// Size and modification time (nanoseconds since the unix epoch) of a file.
+fn file_stamp(path: &str) -> Result<(u64, u128)> {
+    let meta = fs::metadata(path)?;
+    let modified = meta.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+    Ok((meta.len(), modified))
+}
+
+impl CSVIndex {
+    /// Scan the CSV file at `path` and record where each record starts. The
+    /// file is streamed, never held in memory as a whole.
+    pub fn build(path: &str) -> Result<CSVIndex> {
+        let (file_len, modified) = file_stamp(path)?;
+        let mut reader = BufReader::with_capacity(1 << 16, File::open(path)?);
+
+        let mut offsets = vec![0];
+        let mut in_quotes = false;
+        let mut position: u64 = 0;
+        let mut buffer = [0u8; 1 << 16];
+
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            for b in &buffer[..read] {
+                position += 1;
+                if *b == b'"' {
+                    in_quotes = !in_quotes;
+                } else if *b == b'\n' && !in_quotes {
+                    offsets.push(position);
+                }
+            }
+        }
+
+        Ok(CSVIndex { file_len, modified, offsets })
+    }
+
+    /// Number of records in the index, including the header.
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Checks to see if the index holds any records.
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Byte offset of the start of record `n`.
+    pub fn offset(&self, n: usize) -> Option<u64> {
+        self.offsets.get(n).copied()
+    }
+
+    /// Byte range of record `n`, without the trailing `\n`. `None` past the
+    /// last record or when the offsets don't fit the file.
+    fn span(&self, n: usize) -> Option<Range<u64>> {
+        let start = *self.offsets.get(n)?;
+        let end = match self.offsets.get(n + 1) {
+            Some(next) => next.checked_sub(1)?,
+            None => self.file_len,
+        };
+        (start <= end).then_some(start..end)
+    }
+
+    /// Checks that the offsets strictly increase and all lie within the file,
+    /// as they do in every index [build](CSVIndex::build) makes.
+    fn check_offsets(&self, path: &str) -> Result<()> {
+        let increasing = self.offsets.windows(2).all(|pair| pair[0] < pair[1]);
+        let within = self.offsets.last().is_none_or(|last| *last <= self.file_len);
+        if !increasing || !within {
+            return Err(
+                Box::new(errors::StaleIndex {
+                    message: format!("Offsets in index {} do not fit the file, rebuild it", path),
+                })
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Checks the CSV file at `path` against the size and modification time
+    /// stored in the index. A stale index must be rebuilt before use.
+    pub fn is_stale(&self, path: &str) -> Result<bool> {
+        let (file_len, modified) = file_stamp(path)?;
+        Ok(file_len != self.file_len || modified != self.modified)
+    }
+
+    /// Write the index to `path`, usually [`index_path`] of the CSV file.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut bytes = Vec::with_capacity(40 + self.offsets.len() * 8);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.file_len.to_le_bytes());
+        bytes.extend_from_slice(&self.modified.to_le_bytes());
+        bytes.extend_from_slice(&(self.offsets.len() as u64).to_le_bytes());
+        for offset in &self.offsets {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+        }
+
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// Read an index previously written with [save](CSVIndex::save).
+    pub fn load(path: &str) -> Result<CSVIndex> {
+        let bytes = fs::read(path)?;
+        let corrupt = || -> Box<dyn std::error::Error> {
+            Box::new(errors::ParseError {
+                message: format!("Unable to read index file {}", path),
+            })
+        };
+
+        if bytes.len() < 40 || &bytes[..8] != MAGIC {
+            return Err(corrupt());
+        }
+
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        let file_len = u64_at(8);
+        let modified = u128::from_le_bytes(bytes[16..32].try_into().unwrap());
+        let expected_len = usize::try_from(u64_at(32))
+            .ok()
+            .and_then(|count| count.checked_mul(8))
+            .and_then(|size| size.checked_add(40));
+
+        if expected_len != Some(bytes.len()) {
+            return Err(
+                Box::new(errors::StaleIndex {
+                    message: format!("Record count in index {} does not match its size, rebuild it", path),
+                })
+            );
+        }
+
+        let offsets = bytes[40..].chunks_exact(8).map(|b| u64::from_le_bytes(b.try_into().unwrap())).collect();
+        let index = CSVIndex { file_len, modified, offsets };
+        index.check_offsets(path)?;
+
+        Ok(index)
+    }
+}
+
+/// Reads individual records or ranges of records straight from disk using a
+/// [`CSVIndex`], without parsing anything else in the file.
+#[derive(Debug)]
+pub struct IndexedReader {
+    file: File,
+    index: CSVIndex,
+}
+
+impl IndexedReader {
+    /// Open the CSV at `path` with its sidecar index ([`index_path`]). Fails
+    /// with [`StaleIndex`](errors::StaleIndex) if the file changed since the
+    /// index was built.
+    pub fn open(path: &str) -> Result<IndexedReader> {
+        let index = CSVIndex::load(&index_path(path))?;
+        IndexedReader::with_index(path, index)
+    }
+
+    /// Like [open](IndexedReader::open), but builds and saves the sidecar index
+    /// when it is missing or stale.
+    pub fn open_or_build(path: &str) -> Result<IndexedReader> {
+        let sidecar = index_path(path);
+        let index = match CSVIndex::load(&sidecar) {
+            Ok(index) if !index.is_stale(path)? => index,
+            _ => {
+                let index = CSVIndex::build(path)?;
+                index.save(&sidecar)?;
+                index
+            }
+        };
+
+        IndexedReader::with_index(path, index)
+    }
+
+    /// Open the CSV at `path` with an index already in memory.
+    pub fn with_index(path: &str, index: CSVIndex) -> Result<IndexedReader> {
+        if index.is_stale(path)? {
+            return Err(
+                Box::new(errors::StaleIndex {
+                    message: format!("Index does not match {}, rebuild it", path),
+                })
+            );
+        }
+
+        index.check_offsets(path)?;
+
+        Ok(IndexedReader { file: File::open(path)?, index })
+    }
+
+    /// Number of records including the header.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Checks to see if there are any records.
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// The index this reader seeks with.
+    pub fn index(&self) -> &CSVIndex {
+        &self.index
+    }
+
+    /// Seek to record `n` and parse only that record.
+    pub fn get(&mut self, n: usize) -> Result<Record> {
+        let end = n.checked_add(1).ok_or_else(|| -> Box<dyn std::error::Error> {
+            Box::new(errors::DataNotFound {
+                message: format!("Record {} out of bounds, file has {} records", n, self.index.len()),
+            })
+        })?;
+        let mut records = self.range(n..end)?;
+        Ok(records.remove(0))
+    }
+
+    /// Seek to the first record of `range` and parse the records within it.
+    pub fn range(&mut self, range: Range<usize>) -> Result<Vec<Record>> {
+        if range.start >= range.end || range.end > self.index.len() {
+            return Err(
+                Box::new(errors::DataNotFound {
+                    message: format!(
+                        "Records {}..{} out of bounds, file has {} records",
+                        range.start, range.end, self.index.len()
+                    ),
+                })
+            );
+        }
+
+        let stale = || -> Box<dyn std::error::Error> {
+            Box::new(errors::StaleIndex {
+                message: format!("Index does not fit records {}..{}, rebuild it", range.start, range.end),
+            })
+        };
+        let first = self.index.span(range.start).ok_or_else(stale)?;
+        let last = self.index.span(range.end - 1).ok_or_else(stale)?;
+        let size = last.end.checked_sub(first.start).ok_or_else(stale)?;
+
+        let mut bytes = vec![0u8; usize::try_from(size)?];
+        self.file.seek(SeekFrom::Start(first.start))?;
+        self.file.read_exact(&mut bytes)?;
+
+        let mut records = Vec::with_capacity(range.len());
+        for n in range.clone() {
+            let span = self.index.span(n).ok_or_else(stale)?;
+            let start = (span.start - first.start) as usize;
+            let end = (span.end - first.start) as usize;
+            records.push(utils::line_to_record(str::from_utf8(&bytes[start..end])?));
+        }
+
+        Ok(records)
+    }
+}
+
+
+#[cfg(test)]
+mod index_tests {
+    use super::*;
+
+    #[test]
+    fn index_round_trip_and_stale_check() {
+        let path = "./tests/test_data/instance_with_quotes.csv";
+        let index = CSVIndex::build(path).expect("failed to build index");
+        assert_eq!(3, index.len());
+        assert_eq!(Some(0), index.offset(0));
+        assert!(!index.is_stale(path).unwrap());
+
+        let mut stale = index.clone();
+        stale.file_len += 1;
+        assert!(stale.is_stale(path).unwrap());
+        assert!(IndexedReader::with_index(path, stale).is_err());
+
+        let sidecar = "./tests/test_data/index_round_trip.csv.idx";
+        index.save(sidecar).expect("failed to save index");
+        let loaded = CSVIndex::load(sidecar).expect("failed to load index");
+        fs::remove_file(sidecar).unwrap_or(());
+        assert_eq!(index, loaded);
+    }
+
+    #[test]
+    fn load_rejects_indexes_that_do_not_fit() {
+        let sidecar = "./tests/test_data/index_that_does_not_fit.csv.idx";
+        let header = |count: u64| {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend_from_slice(&10u64.to_le_bytes());
+            bytes.extend_from_slice(&0u128.to_le_bytes());
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes
+        };
+        let with_offsets = |offsets: &[u64]| {
+            let mut bytes = header(offsets.len() as u64);
+            for offset in offsets {
+                bytes.extend_from_slice(&offset.to_le_bytes());
+            }
+            bytes
+        };
+
+        for bytes in [header(u64::MAX), header(u64::MAX / 8 + 1), with_offsets(&[0, 4, 4]), with_offsets(&[0, 6, 3]), with_offsets(&[0, 11])] {
+            fs::write(sidecar, bytes).unwrap();
+            let err = CSVIndex::load(sidecar).unwrap_err();
+            assert!(err.is::<errors::StaleIndex>(), "{}", err);
+        }
+        fs::write(sidecar, with_offsets(&[0, 4, 10])).unwrap();
+        assert!(CSVIndex::load(sidecar).is_ok());
+        fs::remove_file(sidecar).unwrap_or(());
+    }
+}
@@ -0,0 +1,333 @@
+use std::{collections::HashMap, fs::{self, File}};
+
+use super::{errors, utils::{self, RecordReader, RecordWriter}, Record, Result};
+
+/// Which records [CSV::join](crate::CSV::join) keeps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    /// Pairs of left and right records with the same key.
+    Inner,
+    /// Inner, plus left records without a match, the right fields empty.
+    Left,
+    /// Inner, plus right records without a match, the left fields empty.
+    Right,
+    /// Inner, plus unmatched records from both sides.
+    Full,
+    /// Left records that have a match, with only the left columns.
+    Semi,
+    /// Left records without a match, with only the left columns.
+    Anti,
+}
+
+/// The joined header and where each of its fields comes from.
+struct JoinPlan {
+    kind: JoinKind,
+    header: Record,
+    left_width: usize,
+    left_keys: Vec<usize>,
+    right_keys: Vec<usize>,
+    right_kept: Vec<usize>,
+}
+
+impl JoinPlan {
+    /// The joined header has every left column followed by the right columns
+    /// that aren't keys. Names on both sides get `suffixes` added, the left
+    /// one to the left column and the right one to the right column.
+    fn new(left: &Record, right: &Record, on: &[(&str, &str)], kind: JoinKind, suffixes: (&str, &str)) -> Result<JoinPlan> {
+        if on.is_empty() {
+            return Err(
+                Box::new(errors::DataNotFound {
+                    message: String::from("No columns to join on"),
+                })
+            );
+        }
+
+        let mut left_keys = Vec::with_capacity(on.len());
+        let mut right_keys = Vec::with_capacity(on.len());
+        for (left_name, right_name) in on {
+            left_keys.push(utils::column_index(std::slice::from_ref(left), left_name)?);
+            right_keys.push(utils::column_index(std::slice::from_ref(right), right_name)?);
+        }
+        let right_kept: Vec<usize> = (0..right.len()).filter(|i| !right_keys.contains(i)).collect();
+
+        let header = match kind {
+            JoinKind::Semi | JoinKind::Anti => left.clone(),
+            _ => {
+                let collides = |name: &String| {
+                    left.contains(name) && right_kept.iter().any(|i| right[*i] == *name)
+                };
+                let mut header: Record = left
+                    .iter()
+                    .map(|name| if collides(name) { format!("{}{}", name, suffixes.0) } else { name.clone() })
+                    .collect();
+                header.extend(right_kept.iter().map(|i| {
+                    let name = &right[*i];
+                    if collides(name) { format!("{}{}", name, suffixes.1) } else { name.clone() }
+                }));
+                header
+            }
+        };
+
+        Ok(JoinPlan { kind, header, left_width: left.len(), left_keys, right_keys, right_kept })
+    }
+
+    fn key(record: &Record, columns: &[usize]) -> Vec<String> {
+        columns.iter().map(|i| record.get(*i).cloned().unwrap_or_default()).collect()
+    }
+
+    /// One joined record. Without a left record the left key columns take the
+    /// values of the right keys, every other missing field is empty.
+    fn combine(&self, left: Option<&Record>, right: Option<&Record>) -> Record {
+        if matches!(self.kind, JoinKind::Semi | JoinKind::Anti) {
+            return left.cloned().unwrap_or_default();
+        }
+
+        let mut record: Record = (0..self.left_width)
+            .map(|i| match left {
+                Some(left) => left.get(i).cloned().unwrap_or_default(),
+                None => self.left_keys
+                    .iter()
+                    .position(|k| *k == i)
+                    .and_then(|k| right.and_then(|right| right.get(self.right_keys[k])))
+                    .cloned()
+                    .unwrap_or_default(),
+            })
+            .collect();
+        record.extend(self.right_kept.iter().map(|i| {
+            right.and_then(|right| right.get(*i)).cloned().unwrap_or_default()
+        }));
+        record
+    }
+}
+
+/// The records of one side held in memory, looked up by key.
+struct Built {
+    records: Vec<Record>,
+    index: HashMap<Vec<String>, Vec<usize>>,
+    matched: Vec<bool>,
+}
+
+impl Built {
+    fn new(records: Vec<Record>, keys: &[usize]) -> Built {
+        let mut index: HashMap<Vec<String>, Vec<usize>> = HashMap::new();
+        for (i, record) in records.iter().enumerate() {
+            index.entry(JoinPlan::key(record, keys)).or_default().push(i);
+        }
+        let matched = vec![false; records.len()];
+        Built { records, index, matched }
+    }
+}
+
+/// Streams the left records past the right side held in `built`. Records come
+/// out in left order, unmatched right records last.
+fn probe_left<N, E>(plan: &JoinPlan, built: &mut Built, mut next_left: N, emit: &mut E) -> Result<()>
+where
+    N: FnMut() -> Result<Option<Record>>,
+    E: FnMut(Record) -> Result<()>,
+{
+    while let Some(left) = next_left()? {
+        let matches = built.index.get(&JoinPlan::key(&left, &plan.left_keys));
+        match (plan.kind, matches) {
+            (JoinKind::Semi, Some(_)) | (JoinKind::Anti, None) => emit(left)?,
+            (JoinKind::Semi, None) | (JoinKind::Anti, Some(_)) => {}
+            (_, Some(matches)) => {
+                for i in matches {
+                    built.matched[*i] = true;
+                    emit(plan.combine(Some(&left), Some(&built.records[*i])))?;
+                }
+            }
+            (JoinKind::Left | JoinKind::Full, None) => emit(plan.combine(Some(&left), None))?,
+            (_, None) => {}
+        }
+    }
+
+    if matches!(plan.kind, JoinKind::Right | JoinKind::Full) {
+        for (i, right) in built.records.iter().enumerate() {
+            if !built.matched[i] {
+                emit(plan.combine(None, Some(right)))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Streams the right records past the left side held in `built`. Records come
+/// out in right order, unmatched left records last. Semi and anti joins only
+/// mark the left records while streaming and keep them in left order.
+fn probe_right<N, E>(plan: &JoinPlan, built: &mut Built, mut next_right: N, emit: &mut E) -> Result<()>
+where
+    N: FnMut() -> Result<Option<Record>>,
+    E: FnMut(Record) -> Result<()>,
+{
+    let semi_or_anti = matches!(plan.kind, JoinKind::Semi | JoinKind::Anti);
+
+    while let Some(right) = next_right()? {
+        match built.index.get(&JoinPlan::key(&right, &plan.right_keys)) {
+            Some(matches) => {
+                for i in matches {
+                    built.matched[*i] = true;
+                    if !semi_or_anti {
+                        emit(plan.combine(Some(&built.records[*i]), Some(&right)))?;
+                    }
+                }
+            }
+            None if matches!(plan.kind, JoinKind::Right | JoinKind::Full) => {
+                emit(plan.combine(None, Some(&right)))?;
+            }
+            None => {}
+        }
+    }
+
+    for (i, left) in built.records.iter().enumerate() {
+        let keep = match plan.kind {
+            JoinKind::Semi => built.matched[i],
+            JoinKind::Anti | JoinKind::Left | JoinKind::Full => !built.matched[i],
+            JoinKind::Inner | JoinKind::Right => false,
+        };
+        if keep {
+            emit(plan.combine(Some(left), None))?;
+        }
+    }
+    Ok(())
+}
+
+/// Hash join of two sets of records, headers included. The right records are
+/// indexed and the left ones looked up in order.
+pub fn join_records(left: &[Record], right: &[Record], on: &[(&str, &str)], kind: JoinKind, suffixes: (&str, &str)) -> Result<Vec<Record>> {
+    let (left_header, right_header) = match (left.first(), right.first()) {
+        (Some(left), Some(right)) => (left, right),
+        _ => {
+            return Err(
+                Box::new(errors::DataNotFound {
+                    message: String::from("Both CSVs need a header to join"),
+                })
+            );
+        }
+    };
+    let plan = JoinPlan::new(left_header, right_header, on, kind, suffixes)?;
+
+    let mut built = Built::new(right[1..].to_vec(), &plan.right_keys);
+    let mut records = left[1..].iter();
+    let mut joined = vec![plan.header.clone()];
+    probe_left(&plan, &mut built, || Ok(records.next().cloned()), &mut |record| {
+        joined.push(record);
+        Ok(())
+    })?;
+
+    Ok(joined)
+}
+
+/// Join the CSV files `left` and `right` into `output` without loading both.
+/// The smaller file is indexed in memory and the larger one streamed past it,
+/// so records come out in the order of the larger file, followed by unmatched
+/// records from the smaller one where `kind` keeps them. Semi and anti joins
+/// always keep left order. Columns are the same as for
+/// [CSV::join_with_suffixes](crate::CSV::join_with_suffixes).
+///
+/// `output` is only replaced once the join is complete, so it may be one of
+/// the inputs. Returns the number of records written, the header excluded.
+pub fn join_files(left: &str, right: &str, output: &str, on: &[(&str, &str)], kind: JoinKind, suffixes: (&str, &str)) -> Result<usize> {
+    if !left.ends_with(".csv") || !right.ends_with(".csv") || !output.ends_with(".csv") {
+        return Err(
+            Box::new(errors::IncorrectFileType {
+                message: String::from("You must use \".csv\" file!")
+            })
+        );
+    }
+
+    let mut left_reader = RecordReader::new(File::open(left)?);
+    let mut right_reader = RecordReader::new(File::open(right)?);
+    let (left_header, right_header) = match (left_reader.next_record()?, right_reader.next_record()?) {
+        (Some(left), Some(right)) => (left, right),
+        _ => {
+            return Err(
+                Box::new(errors::DataNotFound {
+                    message: format!("Both {} and {} need a header to join", left, right),
+                })
+            );
+        }
+    };
+    let plan = JoinPlan::new(&left_header, &right_header, on, kind, suffixes)?;
+
+    let mut writer = RecordWriter::new(output)?;
+    writer.write(&plan.header)?;
+    let mut emit = |record: Record| writer.write(&record);
+
+    if fs::metadata(right)?.len() <= fs::metadata(left)?.len() {
+        let mut built = Built::new(read_rest(&mut right_reader)?, &plan.right_keys);
+        probe_left(&plan, &mut built, || left_reader.next_record(), &mut emit)?;
+    } else {
+        let mut built = Built::new(read_rest(&mut left_reader)?, &plan.left_keys);
+        probe_right(&plan, &mut built, || right_reader.next_record(), &mut emit)?;
+    }
+
+    Ok(writer.finish()? - 1)
+}
+
+fn read_rest(reader: &mut RecordReader<File>) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    while let Some(record) = reader.next_record()? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+
+#[cfg(test)]
+mod join_tests {
+    use super::*;
+
+    fn records(rows: &[&[&str]]) -> Vec<Record> {
+        rows.iter().map(|row| row.iter().map(|f| f.to_string()).collect()).collect()
+    }
+
+    fn customers() -> Vec<Record> {
+        records(&[&["id", "name"], &["1", "ann"], &["2", "bob"], &["3", "cy"]])
+    }
+
+    fn orders() -> Vec<Record> {
+        records(&[&["customer_id", "name", "total"], &["2", "pen", "3"], &["1", "ink", "7"], &["2", "pad", "4"], &["9", "cup", "5"]])
+    }
+
+    fn sorted(mut records: Vec<Record>) -> Vec<Record> {
+        records[1..].sort();
+        records
+    }
+
+    #[test]
+    fn join_kinds() {
+        let on = [("id", "customer_id")];
+        let join = |kind| join_records(&customers(), &orders(), &on, kind, ("_left", "_right")).unwrap();
+
+        let header = &["id", "name_left", "name_right", "total"];
+        assert_eq!(records(&[header, &["1", "ann", "ink", "7"], &["2", "bob", "pen", "3"], &["2", "bob", "pad", "4"]]), join(JoinKind::Inner));
+        assert_eq!(records(&[header, &["1", "ann", "ink", "7"], &["2", "bob", "pen", "3"], &["2", "bob", "pad", "4"], &["3", "cy", "", ""]]), join(JoinKind::Left));
+        assert_eq!(records(&[header, &["1", "ann", "ink", "7"], &["2", "bob", "pen", "3"], &["2", "bob", "pad", "4"], &["9", "", "cup", "5"]]), join(JoinKind::Right));
+        assert_eq!(5, join(JoinKind::Full).len() - 1);
+        assert_eq!(records(&[&["id", "name"], &["1", "ann"], &["2", "bob"]]), join(JoinKind::Semi));
+        assert_eq!(records(&[&["id", "name"], &["3", "cy"]]), join(JoinKind::Anti));
+
+        assert!(join_records(&customers(), &orders(), &[("id", "id")], JoinKind::Inner, ("", "")).is_err());
+        assert!(join_records(&customers(), &orders(), &[], JoinKind::Inner, ("", "")).is_err());
+    }
+
+    #[test]
+    fn either_side_built() {
+        let on = [("id", "customer_id")];
+        for kind in [JoinKind::Inner, JoinKind::Left, JoinKind::Right, JoinKind::Full, JoinKind::Semi, JoinKind::Anti] {
+            let plan = JoinPlan::new(&customers()[0], &orders()[0], &on, kind, ("_l", "_r")).unwrap();
+
+            let mut from_right = vec![plan.header.clone()];
+            let mut built = Built::new(orders()[1..].to_vec(), &plan.right_keys);
+            let mut left = customers().into_iter().skip(1);
+            probe_left(&plan, &mut built, || Ok(left.next()), &mut |r| { from_right.push(r); Ok(()) }).unwrap();
+
+            let mut from_left = vec![plan.header.clone()];
+            let mut built = Built::new(customers()[1..].to_vec(), &plan.left_keys);
+            let mut right = orders().into_iter().skip(1);
+            probe_right(&plan, &mut built, || Ok(right.next()), &mut |r| { from_left.push(r); Ok(()) }).unwrap();
+
+            assert_eq!(sorted(from_right), sorted(from_left), "{:?}", kind);
+        }
+    }
+}
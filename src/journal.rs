@@ -0,0 +1,603 @@
+use std::fs;
+
+use super::{errors, Record, Result};
+
+/// A single reversible change to the records of a [`CSV`](crate::CSV). Every
+/// edit carries what it removes or overwrites, so it can be undone and so a
+/// replay can check it is being applied to the same data it was made on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    /// Insert `records` starting at index `row`.
+    InsertRecords { row: usize, records: Vec<Record> },
+    /// Remove `records`, found starting at index `row`.
+    RemoveRecords { row: usize, records: Vec<Record> },
+    /// Replace `old`, found starting at index `row`, with `new`, which may
+    /// hold a different number of records.
+    ReplaceRecords { row: usize, old: Vec<Record>, new: Vec<Record> },
+    /// Overwrite the field at `row`, `column` holding `old` with `new`.
+    UpdateCell { row: usize, column: usize, old: String, new: String },
+    /// Insert a field at `column` into every record, `values` holding one
+    /// field per record (header included).
+    InsertColumn { column: usize, values: Vec<String> },
+    /// Remove the field at `column` from every record, `values` holding the
+    /// removed fields.
+    RemoveColumn { column: usize, values: Vec<String> },
+    /// Insert fields at `columns`, in ascending order, into every record.
+    /// `values` holds one list per record (header included) with a field per
+    /// column, `None` where the record is too short to have the column.
+    InsertColumns { columns: Vec<usize>, values: Vec<Vec<Option<String>>> },
+    /// Remove the fields at `columns`, in ascending order, from every record,
+    /// `values` holding the removed fields the same way as for
+    /// [`InsertColumns`](Edit::InsertColumns).
+    RemoveColumns { columns: Vec<usize>, values: Vec<Vec<Option<String>>> },
+    /// Rearrange the first `order.len()` fields of every record so that field
+    /// `i` becomes the field previously at `order[i]`.
+    ReorderColumns { order: Vec<usize> },
+    /// Rearrange the records so that record `i` becomes the record previously
+    /// at `order[i]`.
+    ReorderRecords { order: Vec<usize> },
+}
+
+fn out_of_bounds(what: String) -> Box<dyn std::error::Error> {
+    Box::new(errors::DataNotFound {
+        message: what,
+    })
+}
+
+fn mismatch(what: &str) -> Box<dyn std::error::Error> {
+    Box::new(errors::DataNotFound {
+        message: format!("{} does not match the data, it was changed since the edit was made", what),
+    })
+}
+
+impl Edit {
+    /// The edit that takes this one back.
+    pub fn inverse(&self) -> Edit {
+        match self.clone() {
+            Edit::InsertRecords { row, records } => Edit::RemoveRecords { row, records },
+            Edit::RemoveRecords { row, records } => Edit::InsertRecords { row, records },
+            Edit::ReplaceRecords { row, old, new } => Edit::ReplaceRecords { row, old: new, new: old },
+            Edit::UpdateCell { row, column, old, new } => Edit::UpdateCell { row, column, old: new, new: old },
+            Edit::InsertColumn { column, values } => Edit::RemoveColumn { column, values },
+            Edit::RemoveColumn { column, values } => Edit::InsertColumn { column, values },
+            Edit::InsertColumns { columns, values } => Edit::RemoveColumns { columns, values },
+            Edit::RemoveColumns { columns, values } => Edit::InsertColumns { columns, values },
+            Edit::ReorderColumns { order } => Edit::ReorderColumns { order: inverse_order(&order) },
+            Edit::ReorderRecords { order } => Edit::ReorderRecords { order: inverse_order(&order) },
+        }
+    }
+
+    /// Apply the edit to `data`. Nothing is changed if the edit does not fit,
+    /// e.g. an index is out of bounds or a value to remove is not there.
+    pub fn apply(&self, data: &mut Vec<Record>) -> Result<()> {
+        match self {
+            Edit::InsertRecords { row, records } => {
+                if *row > data.len() {
+                    return Err(out_of_bounds(format!("Cannot insert at record {}, CSV has {} records", row, data.len())));
+                }
+                data.splice(*row..*row, records.iter().cloned());
+            }
+            Edit::RemoveRecords { row, records } => {
+                let end = row + records.len();
+                if end > data.len() {
+                    return Err(out_of_bounds(format!("Cannot remove records {}..{}, CSV has {} records", row, end, data.len())));
+                }
+                if data[*row..end] != records[..] {
+                    return Err(mismatch("Records to remove"));
+                }
+                data.drain(*row..end);
+            }
+            Edit::ReplaceRecords { row, old, new } => {
+                let end = row + old.len();
+                if end > data.len() {
+                    return Err(out_of_bounds(format!("Cannot replace records {}..{}, CSV has {} records", row, end, data.len())));
+                }
+                if data[*row..end] != old[..] {
+                    return Err(mismatch("Records to replace"));
+                }
+                data.splice(*row..end, new.iter().cloned());
+            }
+            Edit::UpdateCell { row, column, old, new } => {
+                let cell = data
+                    .get_mut(*row)
+                    .and_then(|record| record.get_mut(*column))
+                    .ok_or_else(|| out_of_bounds(format!("No field at record {}, column {}", row, column)))?;
+                if cell != old {
+                    return Err(mismatch("Field to update"));
+                }
+                *cell = new.clone();
+            }
+            Edit::InsertColumn { column, values } => {
+                if values.len() != data.len() {
+                    return Err(mismatch("Number of column values"));
+                }
+                if data.iter().any(|record| record.len() < *column) {
+                    return Err(out_of_bounds(format!("Cannot insert column {}, a record is too short", column)));
+                }
+                for (record, value) in data.iter_mut().zip(values) {
+                    record.insert(*column, value.clone());
+                }
+            }
+            Edit::RemoveColumn { column, values } => {
+                if values.len() != data.len() {
+                    return Err(mismatch("Number of column values"));
+                }
+                let current = data.iter().map(|record| record.get(*column));
+                if !current.zip(values).all(|(field, value)| field == Some(value)) {
+                    return Err(mismatch("Column to remove"));
+                }
+                for record in data.iter_mut() {
+                    record.remove(*column);
+                }
+            }
+            Edit::InsertColumns { columns, values } => {
+                check_columns(columns, values, data.len())?;
+                // A field can only go where the record reaches, a missing
+                // one only where it doesn't.
+                for (record, fields) in data.iter().zip(values) {
+                    let mut len = record.len();
+                    for (column, value) in columns.iter().zip(fields) {
+                        match value {
+                            Some(_) if *column > len => {
+                                return Err(out_of_bounds(format!("Cannot insert column {}, a record is too short", column)));
+                            }
+                            Some(_) => len += 1,
+                            None if *column < len => return Err(mismatch("Missing field to insert")),
+                            None => (),
+                        }
+                    }
+                }
+                for (record, fields) in data.iter_mut().zip(values) {
+                    for (column, value) in columns.iter().zip(fields) {
+                        if let Some(value) = value {
+                            record.insert(*column, value.clone());
+                        }
+                    }
+                }
+            }
+            Edit::RemoveColumns { columns, values } => {
+                check_columns(columns, values, data.len())?;
+                let matches = data.iter().zip(values).all(|(record, fields)| {
+                    columns.iter().zip(fields).all(|(column, value)| record.get(*column) == value.as_ref())
+                });
+                if !matches {
+                    return Err(mismatch("Columns to remove"));
+                }
+                for record in data.iter_mut() {
+                    for column in columns.iter().rev() {
+                        if *column < record.len() {
+                            record.remove(*column);
+                        }
+                    }
+                }
+            }
+            Edit::ReorderColumns { order } => {
+                if !is_permutation(order) {
+                    return Err(mismatch("Column order"));
+                }
+                if data.iter().any(|record| record.len() < order.len()) {
+                    return Err(out_of_bounds(format!("Cannot reorder {} columns, a record is too short", order.len())));
+                }
+                for record in data.iter_mut() {
+                    let reordered: Vec<String> = order.iter().map(|from| std::mem::take(&mut record[*from])).collect();
+                    record.splice(..order.len(), reordered);
+                }
+            }
+            Edit::ReorderRecords { order } => {
+                if order.len() != data.len() || !is_permutation(order) {
+                    return Err(mismatch("Record order"));
+                }
+                let mut old: Vec<Record> = std::mem::take(data);
+                *data = order.iter().map(|from| std::mem::take(&mut old[*from])).collect();
+            }
+        }
+        Ok(())
+    }
+
+    fn to_line(&self) -> String {
+        let mut tokens: Vec<String> = Vec::new();
+        match self {
+            Edit::InsertRecords { row, records } | Edit::RemoveRecords { row, records } => {
+                let kind = if matches!(self, Edit::InsertRecords { .. }) { "insert_records" } else { "remove_records" };
+                tokens.push(kind.to_string());
+                tokens.push(row.to_string());
+                tokens.extend(records.iter().map(|record| encode_list(record)));
+            }
+            Edit::ReplaceRecords { row, old, new } => {
+                tokens.push("replace_records".to_string());
+                tokens.push(row.to_string());
+                tokens.push(old.len().to_string());
+                tokens.extend(old.iter().chain(new).map(|record| encode_list(record)));
+            }
+            Edit::UpdateCell { row, column, old, new } => {
+                tokens.push("update_cell".to_string());
+                tokens.push(row.to_string());
+                tokens.push(column.to_string());
+                tokens.push(escape(old));
+                tokens.push(escape(new));
+            }
+            Edit::InsertColumn { column, values } | Edit::RemoveColumn { column, values } => {
+                let kind = if matches!(self, Edit::InsertColumn { .. }) { "insert_column" } else { "remove_column" };
+                tokens.push(kind.to_string());
+                tokens.push(column.to_string());
+                tokens.push(encode_list(values));
+            }
+            Edit::InsertColumns { columns, values } | Edit::RemoveColumns { columns, values } => {
+                let kind = if matches!(self, Edit::InsertColumns { .. }) { "insert_columns" } else { "remove_columns" };
+                tokens.push(kind.to_string());
+                tokens.push(encode_list(&columns.iter().map(|i| i.to_string()).collect::<Vec<String>>()));
+                tokens.extend(values.iter().map(|fields| encode_optional_list(fields)));
+            }
+            Edit::ReorderColumns { order } | Edit::ReorderRecords { order } => {
+                let kind = if matches!(self, Edit::ReorderColumns { .. }) { "reorder_columns" } else { "reorder_records" };
+                tokens.push(kind.to_string());
+                tokens.push(encode_list(&order.iter().map(|i| i.to_string()).collect::<Vec<String>>()));
+            }
+        }
+        tokens.join("\t")
+    }
+
+    fn from_line(line: &str) -> Result<Edit> {
+        let invalid = || -> Box<dyn std::error::Error> {
+            Box::new(errors::ParseError {
+                message: format!("Invalid journal line: {}", line),
+            })
+        };
+
+        let tokens: Vec<Vec<String>> = split_escaped(line);
+        let single = |i: usize| -> Result<&str> {
+            match tokens.get(i) {
+                Some(list) if list.len() == 1 => Ok(&list[0]),
+                _ => Err(invalid()),
+            }
+        };
+        let number = |i: usize| -> Result<usize> { single(i)?.parse().map_err(|_| invalid()) };
+        let list = |i: usize| -> Result<Vec<String>> { decode_list(&tokens[i]).ok_or_else(invalid) };
+        let lists = |from: usize| -> Result<Vec<Vec<String>>> { (from..tokens.len()).map(list).collect() };
+
+        let edit = match single(0)? {
+            "insert_records" => Edit::InsertRecords { row: number(1)?, records: lists(2)? },
+            "remove_records" => Edit::RemoveRecords { row: number(1)?, records: lists(2)? },
+            "replace_records" if tokens.len() >= 3 => {
+                let mut old = lists(3)?;
+                let count = number(2)?;
+                if count > old.len() {
+                    return Err(invalid());
+                }
+                let new = old.split_off(count);
+                Edit::ReplaceRecords { row: number(1)?, old, new }
+            }
+            "update_cell" if tokens.len() == 5 => Edit::UpdateCell {
+                row: number(1)?,
+                column: number(2)?,
+                old: single(3)?.to_string(),
+                new: single(4)?.to_string(),
+            },
+            "insert_column" if tokens.len() == 3 => Edit::InsertColumn { column: number(1)?, values: list(2)? },
+            "remove_column" if tokens.len() == 3 => Edit::RemoveColumn { column: number(1)?, values: list(2)? },
+            "insert_columns" | "remove_columns" if tokens.len() >= 2 => {
+                let columns = list(1)?
+                    .iter()
+                    .map(|i| i.parse().map_err(|_| invalid()))
+                    .collect::<Result<Vec<usize>>>()?;
+                let values = (2..tokens.len())
+                    .map(|i| decode_optional_list(&tokens[i]).ok_or_else(invalid))
+                    .collect::<Result<Vec<Vec<Option<String>>>>>()?;
+                if tokens[0][0] == "insert_columns" {
+                    Edit::InsertColumns { columns, values }
+                } else {
+                    Edit::RemoveColumns { columns, values }
+                }
+            }
+            "reorder_columns" | "reorder_records" if tokens.len() == 2 => {
+                let order = list(1)?
+                    .iter()
+                    .map(|i| i.parse().map_err(|_| invalid()))
+                    .collect::<Result<Vec<usize>>>()?;
+                if tokens[0][0] == "reorder_columns" {
+                    Edit::ReorderColumns { order }
+                } else {
+                    Edit::ReorderRecords { order }
+                }
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(edit)
+    }
+}
+
+/// Columns must be in ascending order and every record needs a field (or a
+/// `None`) for each of them.
+fn check_columns(columns: &[usize], values: &[Vec<Option<String>>], records: usize) -> Result<()> {
+    if !columns.windows(2).all(|pair| pair[0] < pair[1]) {
+        return Err(mismatch("Column order"));
+    }
+    if values.len() != records || values.iter().any(|fields| fields.len() != columns.len()) {
+        return Err(mismatch("Number of column values"));
+    }
+    Ok(())
+}
+
+fn is_permutation(order: &[usize]) -> bool {
+    let mut seen = vec![false; order.len()];
+    for from in order {
+        if *from >= order.len() || seen[*from] {
+            return false;
+        }
+        seen[*from] = true;
+    }
+    true
+}
+
+fn inverse_order(order: &[usize]) -> Vec<usize> {
+    let mut inverse = vec![0; order.len()];
+    for (i, from) in order.iter().enumerate() {
+        inverse[*from] = i;
+    }
+    inverse
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            ',' => escaped.push_str("\\,"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The number of values followed by the values, all comma separated, so an
+/// empty list and a list of one empty value read back differently.
+fn encode_list(values: &[String]) -> String {
+    let mut encoded = values.len().to_string();
+    for value in values {
+        encoded.push(',');
+        encoded.push_str(&escape(value));
+    }
+    encoded
+}
+
+/// Like [`encode_list`], each value marked with `+`, or written as `-` when
+/// it is `None`.
+fn encode_optional_list(values: &[Option<String>]) -> String {
+    let marked: Vec<String> = values
+        .iter()
+        .map(|value| match value {
+            Some(value) => format!("+{}", value),
+            None => String::from("-"),
+        })
+        .collect();
+    encode_list(&marked)
+}
+
+/// The values of a token written by [`encode_optional_list`].
+fn decode_optional_list(token: &[String]) -> Option<Vec<Option<String>>> {
+    decode_list(token)?
+        .into_iter()
+        .map(|value| match value.strip_prefix('+') {
+            Some(value) => Some(Some(value.to_string())),
+            None if value == "-" => Some(None),
+            None => None,
+        })
+        .collect()
+}
+
+/// The values of a token written by [`encode_list`], `None` if the count
+/// does not match.
+fn decode_list(token: &[String]) -> Option<Vec<String>> {
+    let (count, values) = token.split_first()?;
+    (count.parse() == Ok(values.len())).then(|| values.to_vec())
+}
+
+/// Splits a journal line into tab separated tokens, each one a comma
+/// separated list of unescaped values.
+fn split_escaped(line: &str) -> Vec<Vec<String>> {
+    let mut tokens = vec![vec![String::new()]];
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        let token = tokens.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => token.last_mut().unwrap().push('\t'),
+                Some('n') => token.last_mut().unwrap().push('\n'),
+                Some('r') => token.last_mut().unwrap().push('\r'),
+                Some(other) => token.last_mut().unwrap().push(other),
+                None => (),
+            },
+            ',' => token.push(String::new()),
+            '\t' => tokens.push(vec![String::new()]),
+            _ => token.last_mut().unwrap().push(c),
+        }
+    }
+    tokens
+}
+
+const JOURNAL_HEADER: &str = "csv-journal 1";
+
+/// History of the [`Edit`]s made to a [`CSV`](crate::CSV) while journaling
+/// is on. Edits that were undone are kept until a new edit is made, so they
+/// can be redone.
+///
+/// A journal can be written to text with [to_text](Journal::to_text) or
+/// [save](Journal::save) and replayed onto a fresh copy of the same file with
+/// [CSV::replay](crate::CSV::replay).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Journal {
+    done: Vec<Edit>,
+    undone: Vec<Edit>,
+    checkpoints: Vec<(String, usize)>,
+}
+
+impl Journal {
+    /// Edits currently applied, oldest first.
+    pub fn edits(&self) -> &[Edit] {
+        &self.done
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    /// Add an edit that was just applied. Anything that was undone can no
+    /// longer be redone.
+    pub fn record(&mut self, edit: Edit) {
+        self.undone.clear();
+        self.checkpoints.retain(|(_, position)| *position <= self.done.len());
+        self.done.push(edit);
+    }
+
+    pub(crate) fn pop_undo(&mut self) -> Option<Edit> {
+        self.done.pop()
+    }
+
+    pub(crate) fn push_undone(&mut self, edit: Edit) {
+        self.undone.push(edit);
+    }
+
+    pub(crate) fn pop_redo(&mut self) -> Option<Edit> {
+        self.undone.pop()
+    }
+
+    pub(crate) fn push_done(&mut self, edit: Edit) {
+        self.done.push(edit);
+    }
+
+    /// Remember the current position under `name`, replacing an earlier
+    /// checkpoint with the same name.
+    pub fn checkpoint(&mut self, name: &str) {
+        self.checkpoints.retain(|(n, _)| n != name);
+        self.checkpoints.push((name.to_string(), self.done.len()));
+    }
+
+    /// Number of applied edits when the checkpoint `name` was made.
+    pub fn checkpoint_position(&self, name: &str) -> Option<usize> {
+        self.checkpoints.iter().find(|(n, _)| n == name).map(|(_, position)| *position)
+    }
+
+    /// The applied edits as text, one edit per line.
+    pub fn to_text(&self) -> String {
+        let mut text = String::from(JOURNAL_HEADER);
+        for edit in &self.done {
+            text.push('\n');
+            text.push_str(&edit.to_line());
+        }
+        text
+    }
+
+    /// Read a journal written with [to_text](Journal::to_text).
+    pub fn from_text(text: &str) -> Result<Journal> {
+        let mut lines = text.lines();
+        if lines.next() != Some(JOURNAL_HEADER) {
+            return Err(
+                Box::new(errors::ParseError {
+                    message: String::from("Not a CSV journal"),
+                })
+            );
+        }
+
+        let mut journal = Journal::default();
+        for line in lines.filter(|line| !line.is_empty()) {
+            journal.done.push(Edit::from_line(line)?);
+        }
+        Ok(journal)
+    }
+
+    /// Write the applied edits to the file at `path`.
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    /// Read a journal from the file at `path`.
+    pub fn load(path: &str) -> Result<Journal> {
+        Journal::from_text(&fs::read_to_string(path)?)
+    }
+}
+
+
+#[cfg(test)]
+mod journal_tests {
+    use super::*;
+
+    #[test]
+    fn edits_and_inverses() {
+        let original: Vec<Record> = vec![
+            vec![String::from("one"), String::from("two")],
+            vec![String::from("three"), String::from("four")],
+        ];
+        let mut data = original.clone();
+
+        let edits = vec![
+            Edit::InsertRecords { row: 1, records: vec![vec![String::from("a"), String::from("b")]] },
+            Edit::UpdateCell { row: 0, column: 1, old: String::from("two"), new: String::from("2") },
+            Edit::InsertColumn { column: 0, values: vec![String::from("x"); 3] },
+            Edit::RemoveColumn { column: 2, values: vec![String::from("2"), String::from("b"), String::from("four")] },
+            Edit::RemoveRecords { row: 0, records: vec![vec![String::from("x"), String::from("one")]] },
+            Edit::ReplaceRecords {
+                row: 0,
+                old: vec![vec![String::from("x"), String::from("a")]],
+                new: vec![vec![String::from("c"), String::from("d")], vec![String::from("e"), String::from("f")]],
+            },
+            Edit::ReplaceRecords {
+                row: 0,
+                old: vec![vec![String::from("c"), String::from("d")], vec![String::from("e"), String::from("f")]],
+                new: vec![vec![String::from("x"), String::from("a")]],
+            },
+            Edit::ReorderColumns { order: vec![1, 0] },
+            Edit::ReorderRecords { order: vec![1, 0] },
+        ];
+        for edit in &edits {
+            edit.apply(&mut data).expect("failed to apply edit");
+        }
+        assert_eq!(vec![vec![String::from("three"), String::from("x")], vec![String::from("a"), String::from("x")]], data);
+
+        for edit in edits.iter().rev() {
+            edit.inverse().apply(&mut data).expect("failed to revert edit");
+        }
+        assert_eq!(original, data);
+
+        let stale = Edit::UpdateCell { row: 0, column: 0, old: String::from("nope"), new: String::new() };
+        assert!(stale.apply(&mut data).is_err());
+        assert_eq!(original, data);
+    }
+
+    #[test]
+    fn journal_text_round_trip() {
+        let mut journal = Journal::default();
+        journal.record(Edit::InsertRecords {
+            row: 0,
+            records: vec![vec![String::from("a,b"), String::from("tab\there")], vec![String::from("new\nline\\")]],
+        });
+        journal.record(Edit::UpdateCell { row: 1, column: 0, old: String::new(), new: String::from("\r") });
+        journal.record(Edit::RemoveColumn { column: 0, values: vec![String::from("a,b"), String::from("c")] });
+        journal.record(Edit::ReorderColumns { order: vec![2, 0, 1] });
+        journal.record(Edit::ReorderRecords { order: vec![0, 1] });
+        journal.record(Edit::InsertRecords { row: 1, records: vec![vec![], vec![String::new()], vec![String::new(); 2]] });
+        journal.record(Edit::InsertColumn { column: 0, values: vec![] });
+        journal.record(Edit::RemoveColumn { column: 0, values: vec![String::new()] });
+        journal.record(Edit::ReorderRecords { order: vec![] });
+        journal.record(Edit::ReplaceRecords { row: 1, old: vec![vec![String::from("x")]], new: vec![vec![], vec![String::new()]] });
+        journal.record(Edit::ReplaceRecords { row: 1, old: vec![], new: vec![] });
+        journal.record(Edit::RemoveColumns {
+            columns: vec![0, 2],
+            values: vec![vec![Some(String::from("-")), Some(String::new())], vec![Some(String::from("+a")), None], vec![None, None]],
+        });
+
+        let text = journal.to_text();
+        assert_eq!(12, text.lines().count() - 1);
+        assert_eq!(journal.edits(), Journal::from_text(&text).expect("failed to parse journal").edits());
+        assert!(Journal::from_text("something else").is_err());
+        assert!(Journal::from_text("csv-journal 1\ninsert_column\t0\t2,a").is_err());
+    }
+}
@@ -1,7 +1,43 @@
-use std::{fs, str, collections::HashMap};
+use std::{fs, str, collections::HashMap, io::{Read, Seek, SeekFrom, Write}};
 
 pub mod errors;
 mod utils;
+mod index;
+mod tracking;
+mod stamp;
+mod lock;
+mod journal;
+mod transaction;
+mod sort;
+mod external_sort;
+mod query;
+mod join;
+mod group;
+mod reshape;
+mod dedup;
+mod diff;
+mod merge;
+mod concat;
+mod split;
+#[cfg(target_os = "linux")]
+mod mmap;
+
+pub use index::{CSVIndex, IndexedReader, index_path};
+pub use lock::{LockMode, LockedCSV, lock_path};
+pub use journal::{Edit, Journal};
+pub use transaction::Transaction;
+pub use sort::{Compare, Nulls, SortKey, SortOrder};
+pub use external_sort::external_sort;
+pub use query::Query;
+pub use join::{JoinKind, join_files};
+pub use group::{Agg, AggFailure, Aggregate, Aggregated, GroupBy};
+pub use dedup::{Keep, dedup_file};
+pub use diff::{CellChange, Diff, RowChange, diff};
+pub use merge::{Conflict, Merge, merge3};
+pub use concat::{HeaderPolicy, concat_files};
+pub use split::{Part, write_parts};
+#[cfg(target_os = "linux")]
+pub use mmap::MappedCSV;
 
 /// This is an alias for the `std::result::Result<T, E>` type.
 /// Nothing speical to see here.
@@ -12,16 +48,27 @@ pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 /// 
 /// *IMPORTANT:* Data within the CSV must be UTF8 encoded. Other endcodings will
 /// not be gauranteed and may cause errors or will crash the program.
+///
+/// Changes made through methods such as [insert_one](CSV::insert_one),
+/// [record_mut](CSV::record_mut) or [data_mut](CSV::data_mut) are tracked and
+/// put the CSV back into an unsaved state. Editing `CSV.data` directly is not
+/// tracked record by record, but it is noticed by comparing the records with
+/// a hash of what was last read or saved, see [check_state](CSV::check_state).
 #[derive(Debug)]
 pub struct CSV<'a> {
     pub path: &'a str,
     pub data: Vec<Record>,
     state: SaveState,
+    saved_len: usize,
+    keep_backup: bool,
+    changes: tracking::ChangeTracker,
+    stamp: Option<stamp::FileStamp>,
+    journal: Option<Journal>,
 }
 
 /// Self explanatory. All new instances of CSV will default to an
 /// unsaved state. Only CSVs that call the [save](CSV::save) method will have
-/// a saved state.
+/// a saved state, until the next tracked change.
 #[derive(Debug)]
 pub enum SaveState {
     Saved,
@@ -35,32 +82,39 @@ pub type Record = Vec<String>;
 
 
 impl<'a> CSV<'a> {
-    
-    /// Creates a blank CSV instance with a desired file path
-    pub fn new(path: &str) -> CSV {
-        utils::path_validate(path);
+
+    fn from_parts(path: &'a str, data: Vec<Record>, saved_len: usize) -> CSV<'a> {
+        let mut changes = tracking::ChangeTracker::default();
+        changes.saved(&data[..saved_len]);
         CSV {
             path,
-            data: Vec::new(),
+            data,
             state: SaveState::Unsaved,
+            saved_len,
+            keep_backup: false,
+            changes,
+            stamp: None,
+            journal: None,
         }
     }
+    
+    /// Creates a blank CSV instance with a desired file path
+    pub fn new(path: &str) -> CSV<'_> {
+        utils::path_validate(path);
+        CSV::from_parts(path, Vec::new(), 0)
+    }
 
     /// Create a new CSV instance when you have data ready to insert
-    pub fn new_with_data(path: &str, data: Vec<Record>) -> CSV {
+    pub fn new_with_data(path: &str, data: Vec<Record>) -> CSV<'_> {
         utils::path_validate(path);
-        CSV {
-            path,
-            data,
-            state: SaveState::Unsaved,
-        }
+        CSV::from_parts(path, data, 0)
     }
 
     /// Create a new CSV instance from an existing CSV file. This method uses
     /// [fs::read_to_string](https://doc.rust-lang.org/std/fs/fn.read_to_string.html)
     /// and appropriately parses in data into a vector of [`Record`]s.
     /// *In it's current state, it cannot read CSV's without a comma delimiter!*
-    pub fn new_from_file(path: &str) -> Result<CSV> {
+    pub fn new_from_file(path: &str) -> Result<CSV<'_>> {
         if !path.ends_with(".csv") {
             return Err(
                 Box::new(errors::IncorrectFileType {
@@ -69,27 +123,155 @@ impl<'a> CSV<'a> {
             );
         }
 
+        let (data, stamp) = CSV::read_file(path, 1)?;
+
+        let saved_len = data.len();
+        let mut csv = CSV::from_parts(path, data, saved_len);
+        csv.stamp = Some(stamp);
+        Ok(csv)
+    }
+
+    /// Same as [new_from_file](CSV::new_from_file), but parses the file across
+    /// `threads` threads using [std::thread::scope](https://doc.rust-lang.org/std/thread/fn.scope.html).
+    /// The file is split at record boundaries (quoted newlines are respected) and
+    /// the records are put back together in their original order. Passing `0` or
+    /// `1` parses on the current thread.
+    pub fn new_from_file_parallel(path: &str, threads: usize) -> Result<CSV<'_>> {
+        if !path.ends_with(".csv") {
+            return Err(
+                Box::new(errors::IncorrectFileType {
+                    message: String::from("You must use \".csv\" file!")
+                })
+            );
+        }
+
+        let (data, stamp) = CSV::read_file(path, threads)?;
+
+        let saved_len = data.len();
+        let mut csv = CSV::from_parts(path, data, saved_len);
+        csv.stamp = Some(stamp);
+        Ok(csv)
+    }
+
+    fn read_file(path: &str, threads: usize) -> Result<(Vec<Record>, stamp::FileStamp)> {
+        let metadata = stamp::FileStamp::metadata(path)?;
         let file_data = fs::read_to_string(path)?;
 
-        let data = utils::raw_csv_to_records(&file_data)?;
+        let data = utils::raw_csv_to_records_parallel(&file_data, threads)?;
 
-        Ok(CSV {
-            path,
-            data,
-            state: SaveState::Unsaved,
-        })
+        Ok((data, stamp::FileStamp::new(metadata, file_data.as_bytes())))
+    }
+
+    /// Throw away the records in memory and read the file at `CSV.path` again.
+    /// Use this after a save was refused with [`FileChanged`](errors::FileChanged)
+    /// to pick up the changes made by someone else.
+    pub fn reload(&mut self) -> Result<()> {
+        let (data, stamp) = CSV::read_file(self.path, 1)?;
+
+        self.data = data;
+        self.saved_len = self.data.len();
+        self.stamp = Some(stamp);
+        self.changes.everything();
+        self.changes.saved(&self.data);
+        self.state = SaveState::Unsaved;
+        if self.journal.is_some() {
+            self.journal = Some(Journal::default());
+        }
+        Ok(())
+    }
+
+    /// Errors with [`FileChanged`](errors::FileChanged) if the file at
+    /// `CSV.path` was changed by someone else since this CSV read or wrote it.
+    fn check_external_changes(&self) -> Result<()> {
+        if let Some(stamp) = &self.stamp {
+            if stamp.changed(self.path)? {
+                return Err(
+                    Box::new(errors::FileChanged {
+                        message: format!("{} was changed on disk since it was read", self.path),
+                    })
+                );
+            }
+        }
+        Ok(())
     }
 
     /// Check the state of a CSV written to memory. Only CSV's that have used the 
     /// 'save' method will have a saved state. It is impotant to note that all CSVs that
     /// are built, even with 'new_from_file', will have an unsaved state by default.
+    /// Any tracked change after a save puts the CSV back into an unsaved state.
+    ///
+    /// Changes made to `CSV.data` directly are found by hashing the records,
+    /// so this reads every record, as do [changed_rows](CSV::changed_rows) and
+    /// [check_unsaved](CSV::check_unsaved).
     pub fn check_state(&self) -> &SaveState {
+        if self.changes.untracked(&self.data, self.saved_len) {
+            return &SaveState::Unsaved;
+        }
         &self.state
     }
 
+    /// Total number of tracked changes made to this CSV since it was created.
+    /// The counter never resets, so it can be compared between two points in
+    /// time to see if anything changed in between.
+    pub fn modifications(&self) -> u64 {
+        self.changes.modifications()
+    }
+
+    /// Indices of the records changed since the last save, in ascending order.
+    /// Returns `None` if [data_mut](CSV::data_mut) was used or `CSV.data` was
+    /// changed directly, as any record could have changed.
+    pub fn changed_rows(&self) -> Option<Vec<usize>> {
+        if self.changes.untracked(&self.data, self.saved_len) {
+            return None;
+        }
+        self.changes.changed_rows()
+    }
+
+    /// Explicit check for unsaved changes, e.g. before dropping a CSV or
+    /// exiting. Returns an [`UnsavedChanges`](errors::UnsavedChanges) error
+    /// describing the changes if there are any.
+    pub fn check_unsaved(&self) -> Result<()> {
+        if self.changes.is_clean() && !self.changes.untracked(&self.data, self.saved_len) {
+            return Ok(());
+        }
+
+        let rows = match self.changed_rows() {
+            Some(rows) => format!("{} record(s)", rows.len()),
+            None => String::from("any record"),
+        };
+        Err(
+            Box::new(errors::UnsavedChanges {
+                message: format!("Unsaved changes to {} in {}", rows, self.path),
+            })
+        )
+    }
+
+    fn mark_changed(&mut self, row: usize) {
+        self.changes.row(row);
+        self.state = SaveState::Unsaved;
+    }
+
+    fn mark_saved(&mut self, metadata: (u64, Option<std::time::SystemTime>), contents: &[u8]) {
+        self.changes.saved(&self.data);
+        self.state = SaveState::Saved;
+        self.saved_len = self.data.len();
+        self.stamp = Some(stamp::FileStamp::new(metadata, contents));
+    }
 
+
+    /// Retreive the last record of the CSV instance. Returns a
+    /// [`DataNotFound`](errors::DataNotFound) error if there are no records.
     pub fn get_last_record(&self) -> Result<&Record> {
-        let last_line = &self.data[&self.data.len() - 1];
+        let last_line = match self.data.last() {
+            Some(record) => record,
+            None => {
+                return Err(
+                    Box::new(errors::DataNotFound {
+                        message: String::from("No records in CSV")
+                    })
+                );
+            }
+        };
         if last_line.is_empty() {
             return Err(
                 Box::new(errors::DataNotFound {
@@ -100,13 +282,30 @@ impl<'a> CSV<'a> {
         Ok(last_line)
     }
 
+    /// Build a [`CSVIndex`] of the file at `CSV.path` as it is on disk, holding
+    /// the byte offset of every record. Unsaved changes in memory are not part
+    /// of the index. Save it with [CSVIndex::save] to [`index_path`] so an
+    /// [`IndexedReader`] can seek straight to a record later.
+    pub fn build_index(&self) -> Result<CSVIndex> {
+        CSVIndex::build(self.path)
+    }
+
     /// Retreive the first line of the CSV instance as a `HashMap`
     /// with the Key as the name of the header and the value as the
     /// index of the location in the `Record` vectors. This creates
     /// an "owned" HashMap. This way manipulation to the headers is
     /// easily accessible if needed.
     pub fn get_headers(&self) -> Result<HashMap<String, usize>> {
-        let first_line = &self.data[0];
+        let first_line = match self.data.first() {
+            Some(record) => record,
+            None => {
+                return Err(
+                    Box::new(errors::DataNotFound {
+                        message: String::from("No records in CSV")
+                    })
+                );
+            }
+        };
         if first_line.is_empty() {
             return Err(
                 Box::new(errors::DataNotFound {
@@ -139,41 +338,898 @@ impl<'a> CSV<'a> {
 
     /// List the number of fields in the first record, limited by 
     /// [std::usize::MAX](https://doc.rust-lang.org/std/usize/constant.MAX.html).
-    pub fn record_len(&self) -> usize {
-        self.data[0].len()
+    /// Returns `None` if there are no records.
+    pub fn record_len(&self) -> Option<usize> {
+        self.data.first().map(|record| record.len())
+    }
+
+    /// The first record, usually the header.
+    pub fn first_record(&self) -> Option<&Record> {
+        self.data.first()
+    }
+
+    /// The record at `row`, counting the header as row 0.
+    pub fn get(&self, row: usize) -> Option<&Record> {
+        self.data.get(row)
+    }
+
+    /// The field at `row`, `column`, counting the header as row 0.
+    pub fn get_cell(&self, row: usize, column: usize) -> Option<&str> {
+        self.data.get(row)?.get(column).map(|field| field.as_str())
+    }
+
+    /// Every field of the column called `name`, header excluded. Records too
+    /// short to have the column are skipped.
+    pub fn column(&self, name: &str) -> Result<Vec<&str>> {
+        let column = self.column_index(name)?;
+        Ok(self.data
+            .iter()
+            .skip(1)
+            .filter_map(|record| record.get(column).map(|field| field.as_str()))
+            .collect())
     }
 
     pub fn insert_one(&mut self, data: Record) {
-        self.data.push(data);
+        self.insert_multi(vec![data]);
     }
 
     pub fn insert_multi(&mut self, data: Vec<Record>) {
+        let start = self.data.len();
+        if let Some(journal) = &mut self.journal {
+            journal.record(Edit::InsertRecords { row: start, records: data.clone() });
+        }
         for x in data {
             self.data.push(x);
         }
+        self.changes.rows(start..self.data.len());
+        self.state = SaveState::Unsaved;
+    }
+
+    /// Position of the column called `name` in the header (first record).
+    pub fn column_index(&self, name: &str) -> Result<usize> {
+        utils::column_index(&self.data, name)
+    }
+
+    fn record_not_found(&self, row: usize) -> Box<dyn std::error::Error> {
+        Box::new(errors::DataNotFound {
+            message: format!("No record {}, CSV has {} records", row, self.data.len()),
+        })
+    }
+
+    fn header_row() -> Box<dyn std::error::Error> {
+        Box::new(errors::DataNotFound {
+            message: String::from("Record 0 is the header, only records after it can be inserted or removed"),
+        })
+    }
+
+    fn header_cell() -> Box<dyn std::error::Error> {
+        Box::new(errors::DataNotFound {
+            message: String::from("Record 0 is the header, use rename_column to change a column name"),
+        })
+    }
+
+    /// Insert a record at `row`, moving the records after it. `row` may be
+    /// `CSV.len()` to add the record at the end. Row 0 is the header, so
+    /// `row` must be at least 1 unless the CSV is empty.
+    pub fn insert_at(&mut self, row: usize, record: Record) -> Result<()> {
+        if row == 0 && !self.data.is_empty() {
+            return Err(CSV::header_row());
+        }
+        if row > self.data.len() {
+            return Err(self.record_not_found(row));
+        }
+        self.apply(Edit::InsertRecords { row, records: vec![record] })
+    }
+
+    /// Remove and return the record at `row`. Row 0 is the header and can't
+    /// be removed.
+    pub fn remove(&mut self, row: usize) -> Result<Record> {
+        if row == 0 {
+            return Err(CSV::header_row());
+        }
+        let record = self.data.get(row).cloned().ok_or_else(|| self.record_not_found(row))?;
+        self.apply(Edit::RemoveRecords { row, records: vec![record.clone()] })?;
+        Ok(record)
+    }
+
+    /// Keep only the records `f` returns `true` for. The header is always kept
+    /// and never passed to `f`. Returns the number of records removed. The
+    /// removal is a single edit, undone in one step.
+    pub fn retain<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(&Record) -> bool,
+    {
+        let keep: Vec<bool> = self.data
+            .iter()
+            .enumerate()
+            .map(|(i, record)| i == 0 || f(record))
+            .collect();
+
+        // Only the records from the first to the last removed one change.
+        let (Some(first), Some(last)) = (keep.iter().position(|k| !k), keep.iter().rposition(|k| !k)) else {
+            return 0;
+        };
+        let old = self.data[first..=last].to_vec();
+        let new: Vec<Record> = old
+            .iter()
+            .zip(&keep[first..=last])
+            .filter(|(_, keep)| **keep)
+            .map(|(record, _)| record.clone())
+            .collect();
+        let removed = old.len() - new.len();
+        let edit = Edit::ReplaceRecords { row: first, old, new };
+
+        let mut keep = keep.into_iter();
+        self.data.retain(|_| keep.next().unwrap_or(true));
+        self.track_edit(&edit);
+        if let Some(journal) = &mut self.journal {
+            journal.record(edit);
+        }
+        removed
+    }
+
+    /// Set the field at `row` in the column named `column` to `value`. Row 0
+    /// is the header, rename columns with [`rename_column`](CSV::rename_column).
+    pub fn update_cell(&mut self, row: usize, column: &str, value: &str) -> Result<()> {
+        if row == 0 {
+            return Err(Self::header_cell());
+        }
+        let column = self.column_index(column)?;
+        let old = self.data
+            .get(row)
+            .ok_or_else(|| self.record_not_found(row))?
+            .get(column)
+            .cloned()
+            .ok_or_else(|| -> Box<dyn std::error::Error> {
+                Box::new(errors::DataNotFound {
+                    message: format!("Record {} has no field {}", row, column),
+                })
+            })?;
+        self.apply(Edit::UpdateCell { row, column, old, new: value.to_string() })
+    }
+
+    /// First record (header excluded) `f` returns `true` for.
+    pub fn find<F>(&self, mut f: F) -> Option<&Record>
+    where
+        F: FnMut(&Record) -> bool,
+    {
+        self.data.iter().skip(1).find(|record| f(record))
+    }
+
+    /// Every record (header excluded) `f` returns `true` for.
+    pub fn find_all<F>(&self, mut f: F) -> Vec<&Record>
+    where
+        F: FnMut(&Record) -> bool,
+    {
+        self.data.iter().skip(1).filter(|record| f(record)).collect()
+    }
+
+    /// Index in `CSV.data` of the first record (header excluded) `f` returns
+    /// `true` for.
+    pub fn position<F>(&self, f: F) -> Option<usize>
+    where
+        F: FnMut(&Record) -> bool,
+    {
+        self.data.iter().skip(1).position(f).map(|i| i + 1)
+    }
+
+    /// Remove duplicate records, keeping the order of the rest. Records are
+    /// duplicates when their fields in the `keys` columns match, or all their
+    /// fields with `None`. `keep` picks which one of them stays. The header
+    /// is never removed. Returns the number of records dropped; like
+    /// [CSV::retain] the removals are tracked and can be undone.
+    pub fn dedup(&mut self, keys: Option<&[&str]>, keep: Keep) -> Result<usize> {
+        let Some(header) = self.data.first() else {
+            return Ok(0);
+        };
+        let columns = dedup::key_columns(header, keys)?;
+        let kept = dedup::kept(&self.data, columns, keep);
+
+        let mut kept = kept.into_iter();
+        Ok(self.retain(|_| kept.next().unwrap_or(true)))
+    }
+
+    /// Indices in `CSV.data` of every record that has a duplicate, compared
+    /// the same way as [CSV::dedup]. These are exactly the records that
+    /// `dedup` with [`Keep::None`] removes.
+    pub fn duplicates(&self, keys: Option<&[&str]>) -> Result<Vec<usize>> {
+        let Some(header) = self.data.first() else {
+            return Ok(Vec::new());
+        };
+        let columns = dedup::key_columns(header, keys)?;
+        Ok(dedup::duplicates(&self.data, columns))
+    }
+
+    /// Add a column called `name` at the end of the header, filling every
+    /// record with `default`.
+    pub fn add_column(&mut self, name: &str, default: &str) -> Result<()> {
+        self.add_column_with(name, |_| default.to_string())
+    }
+
+    /// Add a column called `name` at the end of the header, filling every
+    /// record with the value `f` computes from it.
+    pub fn add_column_with<F>(&mut self, name: &str, f: F) -> Result<()>
+    where
+        F: FnMut(&Record) -> String,
+    {
+        let column = self.data.first().map(|header| header.len()).unwrap_or(0);
+        self.insert_column_at_with(column, name, f)
+    }
+
+    /// Insert a column called `name` at position `column`, filling every
+    /// record with `default`.
+    pub fn insert_column_at(&mut self, column: usize, name: &str, default: &str) -> Result<()> {
+        self.insert_column_at_with(column, name, |_| default.to_string())
+    }
+
+    /// Insert a column called `name` at position `column`, filling every
+    /// record with the value `f` computes from it. The header is not passed
+    /// to `f`. Every record must have at least `column` fields and the header
+    /// must not have a column called `name` yet.
+    pub fn insert_column_at_with<F>(&mut self, column: usize, name: &str, mut f: F) -> Result<()>
+    where
+        F: FnMut(&Record) -> String,
+    {
+        if self.data.is_empty() {
+            return Err(
+                Box::new(errors::DataNotFound {
+                    message: String::from("No header to add a column to")
+                })
+            );
+        }
+        self.check_new_column(name)?;
+
+        let values = self.data
+            .iter()
+            .enumerate()
+            .map(|(i, record)| if i == 0 { name.to_string() } else { f(record) })
+            .collect();
+        self.apply(Edit::InsertColumn { column, values })
+    }
+
+    /// Remove the columns with the given names from the header and every
+    /// record, as a single edit. Records too short to have a column are left
+    /// as they are. Nothing is removed if any of the names is unknown.
+    pub fn drop_columns(&mut self, names: &[&str]) -> Result<()> {
+        let mut columns = names
+            .iter()
+            .map(|name| self.column_index(name))
+            .collect::<Result<Vec<usize>>>()?;
+        columns.sort_unstable();
+        columns.dedup();
+
+        let values = self.data
+            .iter()
+            .map(|record| columns.iter().map(|column| record.get(*column).cloned()).collect())
+            .collect();
+        self.apply(Edit::RemoveColumns { columns, values })
+    }
+
+    /// Rename the column `from` to `to`. Fails if `from` is unknown or a
+    /// column called `to` already exists.
+    pub fn rename_column(&mut self, from: &str, to: &str) -> Result<()> {
+        let column = self.column_index(from)?;
+        if from != to {
+            self.check_new_column(to)?;
+        }
+        self.apply(Edit::UpdateCell { row: 0, column, old: from.to_string(), new: to.to_string() })
+    }
+
+    fn check_new_column(&self, name: &str) -> Result<()> {
+        if self.column_index(name).is_ok() {
+            return Err(
+                Box::new(errors::DuplicateKey {
+                    message: format!("A column named \"{}\" already exists", name),
+                })
+            );
+        }
+        Ok(())
+    }
+
+    /// Move the named columns to the front, in the given order. Columns not
+    /// named keep their order after them. Missing fields in ragged records
+    /// become empty strings, the same as for [select](CSV::select).
+    pub fn reorder_columns(&mut self, names: &[&str]) -> Result<()> {
+        let header_len = self.data.first().map(|header| header.len()).unwrap_or(0);
+        let mut order = Vec::with_capacity(header_len);
+        for name in names {
+            let column = self.column_index(name)?;
+            if order.contains(&column) {
+                return Err(
+                    Box::new(errors::DuplicateKey {
+                        message: format!("Column \"{}\" is named more than once", name),
+                    })
+                );
+            }
+            order.push(column);
+        }
+        for column in 0..header_len {
+            if !order.contains(&column) {
+                order.push(column);
+            }
+        }
+
+        if self.data.iter().all(|record| record.len() >= header_len) {
+            return self.apply(Edit::ReorderColumns { order });
+        }
+
+        // Short records have to be filled in, which a plain reorder can't
+        // take back, so the records are replaced as a whole.
+        let reordered = self.data
+            .iter()
+            .map(|record| {
+                let mut reordered: Record = order
+                    .iter()
+                    .map(|column| record.get(*column).cloned().unwrap_or_default())
+                    .collect();
+                reordered.extend(record.iter().skip(header_len).cloned());
+                reordered
+            })
+            .collect();
+        self.apply(Edit::ReplaceRecords { row: 0, old: self.data.clone(), new: reordered })
+    }
+
+    /// A new CSV holding only the named columns, in the given order. The new
+    /// CSV has the same path, change `CSV.path` before saving it unless it
+    /// should replace this file. Missing fields in ragged records become
+    /// empty strings.
+    pub fn select(&self, names: &[&str]) -> Result<CSV<'a>> {
+        let columns = names
+            .iter()
+            .map(|name| self.column_index(name))
+            .collect::<Result<Vec<usize>>>()?;
+
+        let data = self.data
+            .iter()
+            .map(|record| {
+                columns
+                    .iter()
+                    .map(|column| record.get(*column).cloned().unwrap_or_default())
+                    .collect()
+            })
+            .collect();
+
+        Ok(CSV::from_parts(self.path, data, 0))
+    }
+
+    /// A new CSV with the header and the records `f` returns `true` for. The
+    /// new CSV has the same path, change `CSV.path` before saving it unless it
+    /// should replace this file.
+    pub fn filter<F>(&self, mut f: F) -> CSV<'a>
+    where
+        F: FnMut(&Record) -> bool,
+    {
+        let data = self.data
+            .iter()
+            .enumerate()
+            .filter(|(i, record)| *i == 0 || f(record))
+            .map(|(_, record)| record.clone())
+            .collect();
+
+        CSV::from_parts(self.path, data, 0)
+    }
+
+    /// Same as [CSV::filter], with the records picked by a [`Query`]
+    /// expression such as `age > 30 && country == 'NL'`. Fails if the
+    /// expression doesn't parse or names a column the header doesn't have.
+    pub fn query(&self, expr: &str) -> Result<CSV<'a>> {
+        let header = self.data.first().ok_or_else(|| -> Box<dyn std::error::Error> {
+            Box::new(errors::DataNotFound {
+                message: String::from("CSV has no header to query"),
+            })
+        })?;
+        let query = Query::parse(expr, header)?;
+
+        Ok(self.filter(|record| query.matches(record)))
+    }
+
+    /// Join with `other` on pairs of (this, other) key columns, using a hash
+    /// join with `other` held in memory. Keys are compared as strings. The
+    /// joined header has every column of this CSV followed by the columns of
+    /// `other` that aren't keys; names found on both sides get `_left` and
+    /// `_right` added. Records come out in the order of this CSV, unmatched
+    /// records of `other` last. The new CSV has the same path as this one.
+    pub fn join(&self, other: &CSV, on: &[(&str, &str)], kind: JoinKind) -> Result<CSV<'a>> {
+        self.join_with_suffixes(other, on, kind, ("_left", "_right"))
+    }
+
+    /// Same as [CSV::join], with the suffixes added to column names found on
+    /// both sides given as (this, other).
+    pub fn join_with_suffixes(&self, other: &CSV, on: &[(&str, &str)], kind: JoinKind, suffixes: (&str, &str)) -> Result<CSV<'a>> {
+        let data = join::join_records(&self.data, &other.data, on, kind, suffixes)?;
+        Ok(CSV::from_parts(self.path, data, 0))
+    }
+
+    /// Group the records by the values of the columns in `names`, to be
+    /// summarised with [GroupBy::agg]. Fails if a column isn't in the header.
+    pub fn group_by(&self, names: &[&str]) -> Result<GroupBy<'_, 'a>> {
+        GroupBy::new(self, names)
+    }
+
+    /// Reshape from wide to long: one record for every field of every
+    /// column in `value_columns`, holding the `id_columns`, the name of the
+    /// column under `var_name` and its field under `value_name`. An empty
+    /// `value_columns` melts every column that isn't an id. The new CSV has
+    /// the same path as this one.
+    pub fn melt(&self, id_columns: &[&str], value_columns: &[&str], var_name: &str, value_name: &str) -> Result<CSV<'a>> {
+        let data = reshape::melt(&self.data, id_columns, value_columns, var_name, value_name)?;
+        Ok(CSV::from_parts(self.path, data, 0))
+    }
+
+    /// Reshape from long to wide: one record for every combination of the
+    /// `index` columns, and one column for every value of the `columns`
+    /// column, in the order they first appear. Each field is the `aggregate`
+    /// of the `values` fields of the records that fall into it, empty where
+    /// no record does. Fields that had to be numbers but weren't are
+    /// reported in [`Aggregated::failures`]. The new CSV has the same path as
+    /// this one.
+    pub fn pivot(&self, index: &[&str], columns: &str, values: &str, aggregate: Aggregate) -> Result<Aggregated<'a>> {
+        let (data, failures) = reshape::pivot(&self.data, index, columns, values, aggregate)?;
+        Ok(Aggregated { csv: CSV::from_parts(self.path, data, 0), failures })
+    }
+
+    /// Concatenate the records of `csvs` in order under one header, combined
+    /// by `policy`. Fields are matched to the combined header by column name.
+    /// The new CSV has the path of the first one. Fails if `csvs` is empty,
+    /// one of them has no header, or with [`HeaderPolicy::Strict`] when a
+    /// header differs from the first.
+    pub fn concat(csvs: &[CSV<'a>], policy: HeaderPolicy) -> Result<CSV<'a>> {
+        let headers = csvs
+            .iter()
+            .map(|csv| {
+                csv.data.first().ok_or_else(|| -> Box<dyn std::error::Error> {
+                    Box::new(errors::DataNotFound {
+                        message: format!("{} has no header", csv.path),
+                    })
+                })
+            })
+            .collect::<Result<Vec<&Record>>>()?;
+        let sources: Vec<&str> = csvs.iter().map(|csv| csv.path).collect();
+        let combined = concat::combined_header(&headers, &sources, policy)?;
+
+        let mut data = vec![combined.clone()];
+        for (csv, header) in csvs.iter().zip(headers) {
+            let columns = concat::column_map(header, &combined);
+            data.extend(csv.data[1..].iter().map(|record| concat::align(record, &columns)));
+        }
+
+        Ok(CSV::from_parts(csvs[0].path, data, 0))
+    }
+
+    /// Split the records into parts of at most `rows` records each, every
+    /// part starting with the header. Write them out with [`write_parts`].
+    /// A CSV without records gives no parts. Fails when `rows` is 0.
+    pub fn split_by_rows(&self, rows: usize) -> Result<Vec<Part<'a>>> {
+        if rows == 0 {
+            return Err(
+                Box::new(errors::DataNotFound {
+                    message: String::from("Parts need at least one record, rows can't be 0"),
+                })
+            );
+        }
+        let Some(header) = self.data.first() else {
+            return Ok(Vec::new());
+        };
+
+        Ok(self.data[1..]
+            .chunks(rows)
+            .enumerate()
+            .map(|(i, records)| Part::new(i + 1, None, header, records.to_vec(), self.path))
+            .collect())
+    }
+
+    /// Split the records into parts that take at most `bytes` bytes once
+    /// written by [`write_parts`], header included. A record too large to fit
+    /// with the header gets a part of its own. A CSV without records gives no
+    /// parts.
+    pub fn split_by_size(&self, bytes: usize) -> Vec<Part<'a>> {
+        let Some(header) = self.data.first() else {
+            return Vec::new();
+        };
+        let header_len = split::written_len(header);
+
+        let mut parts = Vec::new();
+        let mut records: Vec<Record> = Vec::new();
+        let mut size = header_len;
+        for record in &self.data[1..] {
+            let len = split::written_len(record);
+            if !records.is_empty() && size + len > bytes {
+                parts.push(Part::new(parts.len() + 1, None, header, std::mem::take(&mut records), self.path));
+                size = header_len;
+            }
+            records.push(record.clone());
+            size += len;
+        }
+        if !records.is_empty() {
+            parts.push(Part::new(parts.len() + 1, None, header, records, self.path));
+        }
+
+        parts
+    }
+
+    /// One part for every value of the column called `column`, holding the
+    /// records with that value in their order, the parts in the order the
+    /// values first appear. Fails if the column isn't in the header.
+    pub fn partition_by(&self, column: &str) -> Result<Vec<Part<'a>>> {
+        let column = self.column_index(column)?;
+        let Some(header) = self.data.first() else {
+            return Ok(Vec::new());
+        };
+
+        // Groups in the order their values first appear, found by value.
+        let mut groups: Vec<(String, Vec<Record>)> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for record in &self.data[1..] {
+            let value = record.get(column).cloned().unwrap_or_default();
+            let position = *positions.entry(value.clone()).or_insert_with(|| {
+                groups.push((value, Vec::new()));
+                groups.len() - 1
+            });
+            groups[position].1.push(record.clone());
+        }
+
+        Ok(groups
+            .into_iter()
+            .enumerate()
+            .map(|(i, (value, records))| Part::new(i + 1, Some(value), header, records, self.path))
+            .collect())
+    }
+
+    /// Swap records and columns, so the header becomes the first column and
+    /// every column becomes a record. Fields missing from ragged records are
+    /// filled with `fill`. Like other changes it is tracked, saved with
+    /// [CSV::save] and can be undone in one step.
+    pub fn transpose(&mut self, fill: &str) -> Result<()> {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+
+        let width = self.data.iter().map(|record| record.len()).max().unwrap_or(0);
+        let transposed: Vec<Record> = (0..width)
+            .map(|column| {
+                self.data
+                    .iter()
+                    .map(|record| record.get(column).map_or_else(|| fill.to_string(), |field| field.clone()))
+                    .collect()
+            })
+            .collect();
+
+        self.apply(Edit::ReplaceRecords { row: 0, old: self.data.clone(), new: transposed })
+    }
+
+    /// Sort the records by one or more columns, the first key that tells two
+    /// records apart decides their order. The header stays where it is and
+    /// the sort is stable, so records that compare equal keep their order.
+    /// Fails without changing anything if a key names an unknown column.
+    pub fn sort_by_columns(&mut self, keys: &[SortKey]) -> Result<()> {
+        let keys = sort::resolve_keys(&self.data, keys)?;
+        if self.data.is_empty() {
+            return Ok(());
+        }
+
+        let mut order = vec![0];
+        order.extend(sort::sorted_order(&self.data[1..], &keys).into_iter().map(|i| i + 1));
+
+        self.apply(Edit::ReorderRecords { order })
+    }
+
+    /// Apply a single [`Edit`] to the records. Edits are checked before they
+    /// are applied: indices must be in bounds and anything removed or
+    /// overwritten must match what the edit expects, otherwise nothing is
+    /// changed and an error is returned. With journaling on, the edit can be
+    /// undone.
+    pub fn apply(&mut self, edit: Edit) -> Result<()> {
+        edit.apply(&mut self.data)?;
+        self.track_edit(&edit);
+        if let Some(journal) = &mut self.journal {
+            journal.record(edit);
+        }
+        Ok(())
+    }
+
+    /// Run a batch of changes all at once or not at all. The closure stages
+    /// changes through the [`Transaction`]; if it returns `Ok` they are
+    /// committed to `CSV.data`, if it returns an error every staged change is
+    /// taken back and the CSV is left as it was, and the closure's error is
+    /// returned. The same happens when the closure panics.
+    ///
+    /// With journaling on, the committed changes are journaled one by one.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Transaction<'_>) -> Result<T>,
+    {
+        let mut tx = Transaction::new(&mut self.data);
+        match f(&mut tx) {
+            Ok(value) => {
+                for edit in tx.into_edits() {
+                    self.track_edit(&edit);
+                    if let Some(journal) = &mut self.journal {
+                        journal.record(edit);
+                    }
+                }
+                Ok(value)
+            }
+            Err(e) => {
+                // The closure's error is what the caller needs to see. Taking
+                // back the staged edits can't fail unless the inverses are
+                // broken, so that is only checked in debug builds.
+                let rollback = tx.rollback();
+                debug_assert!(rollback.is_ok(), "rollback failed after {}: {:?}", e, rollback);
+                Err(e)
+            }
+        }
+    }
+
+    fn track_edit(&mut self, edit: &Edit) {
+        match edit {
+            Edit::InsertRecords { row, records } => {
+                self.changes.inserted(*row, records.len());
+                if *row < self.saved_len {
+                    self.saved_len += records.len();
+                }
+            }
+            Edit::RemoveRecords { row, records } => {
+                let were_saved = *row < self.saved_len;
+                self.changes.removed(*row, records.len(), were_saved);
+                if were_saved {
+                    self.saved_len -= records.len().min(self.saved_len - row);
+                }
+            }
+            Edit::ReplaceRecords { row, old, new } => {
+                let were_saved = *row < self.saved_len;
+                self.changes.replaced(*row, old.len(), new.len(), were_saved);
+                if were_saved {
+                    self.saved_len -= old.len().min(self.saved_len - row);
+                    self.saved_len += new.len();
+                }
+            }
+            Edit::UpdateCell { row, .. } => self.changes.row(*row),
+            Edit::InsertColumn { .. }
+            | Edit::RemoveColumn { .. }
+            | Edit::InsertColumns { .. }
+            | Edit::RemoveColumns { .. }
+            | Edit::ReorderColumns { .. }
+            | Edit::ReorderRecords { .. } => {
+                self.changes.rows(0..self.data.len())
+            }
+        }
+        self.state = SaveState::Unsaved;
+    }
+
+    /// Start recording every change made through [insert_one](CSV::insert_one),
+    /// [insert_multi](CSV::insert_multi) and [apply](CSV::apply) in a
+    /// [`Journal`], so they can be undone and redone. Changes made through
+    /// [record_mut](CSV::record_mut), [data_mut](CSV::data_mut) or `CSV.data`
+    /// are not recorded and can make undoing fail.
+    pub fn start_journal(&mut self) {
+        if self.journal.is_none() {
+            self.journal = Some(Journal::default());
+        }
+    }
+
+    /// Stop journaling and hand back the journal.
+    pub fn stop_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    /// The current journal, if journaling is on.
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    fn no_journal_error() -> Box<dyn std::error::Error> {
+        Box::new(errors::DataNotFound {
+            message: String::from("Journaling is off, call start_journal first"),
+        })
+    }
+
+    /// Take back the latest journaled edit.
+    pub fn undo(&mut self) -> Result<()> {
+        let journal = self.journal.as_mut().ok_or_else(CSV::no_journal_error)?;
+        let edit = journal.pop_undo().ok_or_else(|| -> Box<dyn std::error::Error> {
+            Box::new(errors::DataNotFound {
+                message: String::from("Nothing to undo"),
+            })
+        })?;
+
+        let inverse = edit.inverse();
+        if let Err(e) = inverse.apply(&mut self.data) {
+            journal.push_done(edit);
+            return Err(e);
+        }
+        journal.push_undone(edit);
+        self.track_edit(&inverse);
+        Ok(())
+    }
+
+    /// Apply the latest undone edit again.
+    pub fn redo(&mut self) -> Result<()> {
+        let journal = self.journal.as_mut().ok_or_else(CSV::no_journal_error)?;
+        let edit = journal.pop_redo().ok_or_else(|| -> Box<dyn std::error::Error> {
+            Box::new(errors::DataNotFound {
+                message: String::from("Nothing to redo"),
+            })
+        })?;
+
+        if let Err(e) = edit.apply(&mut self.data) {
+            journal.push_undone(edit);
+            return Err(e);
+        }
+        journal.push_done(edit.clone());
+        self.track_edit(&edit);
+        Ok(())
+    }
+
+    /// Mark the current point in the journal as `name`, to return to it
+    /// later with [undo_to_checkpoint](CSV::undo_to_checkpoint).
+    pub fn checkpoint(&mut self, name: &str) -> Result<()> {
+        let journal = self.journal.as_mut().ok_or_else(CSV::no_journal_error)?;
+        journal.checkpoint(name);
+        Ok(())
+    }
+
+    /// Undo every edit made after the checkpoint `name`.
+    pub fn undo_to_checkpoint(&mut self, name: &str) -> Result<()> {
+        let journal = self.journal.as_ref().ok_or_else(CSV::no_journal_error)?;
+        let position = journal.checkpoint_position(name).ok_or_else(|| -> Box<dyn std::error::Error> {
+            Box::new(errors::DataNotFound {
+                message: format!("No checkpoint named {}", name),
+            })
+        })?;
+
+        while self.journal.as_ref().is_some_and(|j| j.edits().len() > position) {
+            self.undo()?;
+        }
+        Ok(())
+    }
+
+    /// Apply every edit of `journal` in order, e.g. to repeat a session of
+    /// edits on a fresh copy of the file. Stops at the first edit that does
+    /// not fit the data.
+    pub fn replay(&mut self, journal: &Journal) -> Result<()> {
+        for edit in journal.edits() {
+            self.apply(edit.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Mutable access to a single record. The record is marked as changed.
+    pub fn record_mut(&mut self, row: usize) -> Option<&mut Record> {
+        if row >= self.data.len() {
+            return None;
+        }
+        self.mark_changed(row);
+        self.data.get_mut(row)
+    }
+
+    /// Mutable access to all records. As any record could be changed, the
+    /// whole CSV is marked as changed.
+    pub fn data_mut(&mut self) -> &mut Vec<Record> {
+        self.changes.everything();
+        self.state = SaveState::Unsaved;
+        &mut self.data
+    }
+
+
+    /// Keep a copy of the previous file as `<path>.bak` every time the CSV is
+    /// saved. Off by default.
+    pub fn keep_backup(&mut self, keep: bool) {
+        self.keep_backup = keep;
     }
 
 
     /// Create or overwrite an existing CSV file with the data
     /// attached to the CSV instance.
+    ///
+    /// The data is written to a temporary file next to `CSV.path`, synced to
+    /// disk and then renamed over the target, so a crash mid-save never leaves
+    /// a half written file behind. The state only becomes [`SaveState::Saved`]
+    /// once the rename succeeded.
+    ///
+    /// If the file was changed by someone else since it was read (or last
+    /// saved), nothing is written and a [`FileChanged`](errors::FileChanged)
+    /// error is returned. Use [force_save](CSV::force_save) to overwrite anyway
+    /// or [reload](CSV::reload) to pick up their changes.
     pub fn save(&mut self) -> Result<()> {
+        self.check_external_changes()?;
+        self.force_save()
+    }
+
+    /// Same as [save](CSV::save), without checking the file for changes
+    /// made by someone else.
+    pub fn force_save(&mut self) -> Result<()> {
         let temp_data = utils::records_to_string(&self.data, ',');
 
-        fs::write(self.path, temp_data)?;
+        utils::write_atomic(self.path, temp_data.as_bytes(), self.keep_backup)?;
 
-        self.state = SaveState::Saved;
+        self.mark_saved(stamp::FileStamp::metadata(self.path)?, temp_data.as_bytes());
         Ok(())   
     }
 
 
+    /// Add the records inserted since the last [save](CSV::save) (or since the
+    /// file was read) to the end of the file at `CSV.path`, without rewriting
+    /// what is already there. Meant for logging style use where a few records
+    /// are added with [insert_one](CSV::insert_one) at a time.
+    ///
+    /// If the file does not exist or is empty, all records are written. Otherwise
+    /// the first record in the file must match the first record of the CSV
+    /// (the header), and the header is never written twice. A missing trailing
+    /// newline in the existing file is added before the new records.
+    ///
+    /// Appending never overwrites anything, so it is not refused when someone
+    /// else changed the file. Their change is still remembered and a later
+    /// [save](CSV::save) will refuse to overwrite it.
+    pub fn append_new_records(&mut self) -> Result<()> {
+        let changed_on_disk = self.check_external_changes().is_err();
+        // Direct changes to records already in the file can't be appended,
+        // those have to stay unsaved.
+        if self.changes.track_untracked(&self.data, self.saved_len) {
+            self.state = SaveState::Unsaved;
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(self.path)?;
+
+        let mut start = self.saved_len.min(self.data.len());
+        let mut prefix = "";
+
+        if file.metadata()?.len() > 0 {
+            let header = utils::read_first_record(&mut file)?;
+            match self.data.first() {
+                Some(first) if *first == header => (),
+                _ => {
+                    return Err(
+                        Box::new(errors::HeaderMismatch {
+                            message: format!("Header of {} does not match the CSV header", self.path),
+                        })
+                    );
+                }
+            }
+            start = start.max(1);
+
+            let mut last = [0u8; 1];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                prefix = "\n";
+            }
+        }
+
+        if start < self.data.len() {
+            let temp_data = utils::records_to_string(&self.data[start..], ',');
+            let appended = format!("{}{}", prefix, temp_data);
+            file.write_all(appended.as_bytes())?;
+
+            if let (false, Some(stamp)) = (changed_on_disk, &mut self.stamp) {
+                stamp.extend(self.path, appended.as_bytes())?;
+            }
+        }
+
+        // Records edited before `start` were not written, those stay unsaved.
+        self.changes.saved_from(self.saved_len.min(start), &self.data);
+        self.saved_len = self.data.len();
+        if self.changes.is_clean() {
+            self.state = SaveState::Saved;
+        }
+        Ok(())
+    }
+
+
     /// Create or overwrite an existing CSV file with the data.
-    /// This method accepts a custom delimiter for your CSV
+    /// This method accepts a custom delimiter for your CSV. Writes are atomic,
+    /// the same as [save](CSV::save).
     pub fn save_custom(&mut self, c: char) -> Result<()> {
+        self.check_external_changes()?;
+
         let temp_data = utils::records_to_string(&self.data, c);
 
-        fs::write(self.path, temp_data)?;
+        utils::write_atomic(self.path, temp_data.as_bytes(), self.keep_backup)?;
 
-        self.state = SaveState::Saved;
+        self.mark_saved(stamp::FileStamp::metadata(self.path)?, temp_data.as_bytes());
         Ok(())
     }
 
@@ -182,6 +1238,8 @@ impl<'a> CSV<'a> {
 
 // Testing CSV files is in done within ./tests/ directory
 #[cfg(test)]
+// The original tests predate these lints and are kept as they were written.
+#[allow(clippy::assertions_on_constants, clippy::unnecessary_to_owned, clippy::useless_vec)]
 mod tests {
     use super::*;
 
@@ -272,6 +1330,34 @@ mod tests {
         
     }
 
+    #[test]
+    fn test_accessors_on_empty_csv() {
+        let csv = CSV::new("test.csv");
+        assert!(csv.get_headers().is_err());
+        assert!(csv.get_last_record().is_err());
+        assert_eq!(None, csv.record_len());
+        assert_eq!(None, csv.first_record());
+        assert_eq!(None, csv.get(0));
+        assert_eq!(None, csv.get_cell(0, 0));
+        assert!(csv.column("Header 1").is_err());
+    }
+
+    #[test]
+    fn test_accessors_with_data() {
+        let csv = CSV::new_with_data("test.csv", vec![
+            vec![String::from("Header 1"), String::from("Header 2")],
+            vec![String::from("Value 1"), String::from("Value 2")],
+            vec![String::from("Value 3")],
+        ]);
+        assert_eq!(Some(&csv.data[0]), csv.first_record());
+        assert_eq!(Some(&csv.data[2]), csv.get(2));
+        assert_eq!(None, csv.get(3));
+        assert_eq!(Some("Value 2"), csv.get_cell(1, 1));
+        assert_eq!(None, csv.get_cell(2, 1));
+        assert_eq!(vec!["Value 1", "Value 3"], csv.column("Header 1").unwrap());
+        assert_eq!(vec!["Value 2"], csv.column("Header 2").unwrap());
+    }
+
     #[test]
     fn test_inserts_lengths() {
         let mut csv = CSV::new("test.csv");
@@ -280,7 +1366,7 @@ mod tests {
         let single_record: Record = vec![String::from("Head 1"), String::from("Head 2")];
         csv.insert_one(single_record);
         assert_eq!(csv.len(), 1);
-        assert_eq!(csv.record_len(), 2);
+        assert_eq!(csv.record_len(), Some(2));
 
         let multi_record: Vec<Record> = vec![
             vec![String::from("Value 1"), String::from("Value 2")],
@@ -291,5 +1377,476 @@ mod tests {
         assert_eq!(csv.len(), 4);
     }
 
+    #[test]
+    fn test_change_tracking() {
+        let mut csv = CSV::new("test.csv");
+        assert_eq!(csv.modifications(), 0);
+        assert_eq!(csv.changed_rows(), Some(vec![]));
+        assert!(csv.check_unsaved().is_ok());
+
+        csv.insert_one(vec![String::from("Head 1"), String::from("Head 2")]);
+        csv.insert_multi(vec![
+            vec![String::from("Value 1"), String::from("Value 2")],
+            vec![String::from("Value 3"), String::from("Value 4")],
+        ]);
+        if let Some(record) = csv.record_mut(1) {
+            record[0] = String::from("Changed");
+        }
+        assert!(csv.record_mut(3).is_none());
+
+        assert_eq!(csv.modifications(), 3);
+        assert_eq!(csv.changed_rows(), Some(vec![0, 1, 2]));
+        assert!(csv.check_unsaved().is_err());
+
+        csv.data_mut().pop();
+        assert_eq!(csv.modifications(), 4);
+        assert_eq!(csv.changed_rows(), None);
+    }
+
+    #[test]
+    fn test_transaction_commit_and_rollback() {
+        let original: Vec<Record> = vec![
+            vec![String::from("Header 1"), String::from("Header 2")],
+            vec![String::from("Value 1"), String::from("Value 2")],
+            vec![String::from("Value 3"), String::from("Value 4")],
+        ];
+        let mut csv = CSV::new_with_data("test.csv", original.clone());
+
+        let failed: Result<()> = csv.transaction(|tx| {
+            tx.insert_one(vec![String::from("Value 5"), String::from("Value 6")])?;
+            tx.remove(1)?;
+            tx.update_cell(1, "Header 1", "Changed")?;
+            assert_eq!(3, tx.len());
+            Err(Box::new(errors::ParseError { message: String::from("validation failed") }))
+        });
+        assert_eq!("validation failed", failed.unwrap_err().downcast::<errors::ParseError>().unwrap().message);
+        assert_eq!(original, csv.data);
+        assert_eq!(0, csv.modifications());
+
+        let removed = csv.transaction(|tx| {
+            tx.update_cell(2, "Header 2", "Changed")?;
+            let record = tx.get(1).cloned();
+            tx.remove(1)?;
+            Ok(record)
+        }).unwrap();
+        assert_eq!(Some(original[1].clone()), removed);
+        assert_eq!(vec![String::from("Value 3"), String::from("Changed")], csv.data[1]);
+        assert_eq!(2, csv.len());
+        assert_eq!(2, csv.modifications());
+
+        let before = csv.data.clone();
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            csv.transaction(|tx| -> Result<()> {
+                tx.insert_one(vec![String::from("Value 7"), String::from("Value 8")])?;
+                tx.update_cell(1, "Header 1", "Changed")?;
+                panic!("validation panicked");
+            })
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(before, csv.data);
+        assert_eq!(2, csv.modifications());
+    }
+
+    #[test]
+    fn test_record_crud() {
+        let mut csv = CSV::new_with_data("test.csv", vec![
+            vec![String::from("name"), String::from("age")],
+            vec![String::from("Ann"), String::from("31")],
+            vec![String::from("Bob"), String::from("45")],
+            vec![String::from("Cy"), String::from("27")],
+            vec![String::from("Di"), String::from("52")],
+        ]);
+
+        csv.insert_at(1, vec![String::from("Al"), String::from("19")]).unwrap();
+        assert!(csv.insert_at(7, vec![String::from("Zed"), String::from("1")]).is_err());
+        assert!(csv.insert_at(0, vec![String::from("Zed"), String::from("1")]).is_err());
+        assert_eq!(String::from("Al"), csv.data[1][0]);
+
+        let age = csv.column_index("age").unwrap();
+        assert!(csv.column_index("height").is_err());
+        assert_eq!(Some(2), csv.position(|r| r[0] == "Ann"));
+        assert_eq!(None, csv.position(|r| r[0] == "name"));
+        assert_eq!(Some(&csv.data[3]), csv.find(|r| r[age].parse::<u32>().unwrap() > 40));
+        assert_eq!(2, csv.find_all(|r| r[age].parse::<u32>().unwrap() > 40).len());
+
+        csv.update_cell(2, "age", "32").unwrap();
+        assert_eq!(String::from("32"), csv.data[2][1]);
+        assert!(csv.update_cell(9, "age", "32").is_err());
+        assert!(csv.update_cell(0, "age", "name").unwrap_err().is::<errors::DataNotFound>());
+        assert!(csv.transaction(|tx| tx.update_cell(0, "age", "name")).is_err());
+        assert_eq!(vec!["name", "age"], csv.data[0]);
+        assert!(csv.update_cell(2, "height", "180").is_err());
+
+        let removed = csv.remove(1).unwrap();
+        assert_eq!(vec![String::from("Al"), String::from("19")], removed);
+        assert!(csv.remove(5).is_err());
+        assert!(csv.remove(0).is_err());
+        assert_eq!(String::from("name"), csv.data[0][0]);
+
+        csv.start_journal();
+        let dropped = csv.retain(|r| r[0] != "Ann" && r[0] != "Bob" && r[0] != "Di");
+        assert_eq!(3, dropped);
+        assert_eq!(vec![vec![String::from("name"), String::from("age")], vec![String::from("Cy"), String::from("27")]], csv.data);
+
+        csv.undo().unwrap();
+        assert_eq!(5, csv.len());
+        assert_eq!(String::from("Ann"), csv.data[1][0]);
+        assert_eq!(String::from("Di"), csv.data[4][0]);
+    }
+
+    #[test]
+    fn test_column_operations() {
+        let mut csv = CSV::new_with_data("test.csv", vec![
+            vec![String::from("name"), String::from("age"), String::from("city")],
+            vec![String::from("Ann"), String::from("31"), String::from("Oslo")],
+            vec![String::from("Bob"), String::from("45"), String::from("Rome")],
+        ]);
+
+        csv.add_column("country", "??").unwrap();
+        csv.add_column_with("initial", |r| r[0][..1].to_string()).unwrap();
+        csv.insert_column_at(0, "id", "0").unwrap();
+        assert_eq!(vec!["id", "name", "age", "city", "country", "initial"], csv.data[0]);
+        assert_eq!(vec!["0", "Bob", "45", "Rome", "??", "B"], csv.data[2]);
+        assert!(csv.add_column("age", "").unwrap_err().is::<errors::DuplicateKey>());
+        assert!(csv.insert_column_at_with(1, "city", |_| String::new()).unwrap_err().is::<errors::DuplicateKey>());
+        assert_eq!(6, csv.data[0].len());
+
+        assert!(csv.drop_columns(&["id", "height"]).is_err());
+        assert_eq!(6, csv.data[1].len());
+        csv.drop_columns(&["id", "country"]).unwrap();
+        assert_eq!(vec!["name", "age", "city", "initial"], csv.data[0]);
+
+        csv.rename_column("initial", "letter").unwrap();
+        assert!(csv.rename_column("letter", "name").unwrap_err().is::<errors::DuplicateKey>());
+        assert!(csv.rename_column("height", "size").is_err());
+        assert_eq!(Ok(3), csv.column_index("letter").map_err(|e| e.to_string()));
+
+        csv.reorder_columns(&["city", "name"]).unwrap();
+        assert!(csv.reorder_columns(&["city", "city"]).unwrap_err().is::<errors::DuplicateKey>());
+        assert_eq!(vec!["city", "name", "age", "letter"], csv.data[0]);
+        assert_eq!(vec!["Oslo", "Ann", "31", "A"], csv.data[1]);
+
+        let selected = csv.select(&["age", "name"]).unwrap();
+        assert_eq!(vec![vec!["age", "name"], vec!["31", "Ann"], vec!["45", "Bob"]], selected.data);
+        assert!(csv.select(&["height"]).is_err());
+    }
+
+    #[test]
+    fn test_column_operations_on_ragged_records() {
+        let original: Vec<Record> = vec![
+            vec![String::from("a"), String::from("b"), String::from("c")],
+            vec![String::from("1"), String::from("2"), String::from("3")],
+            vec![String::from("4")],
+        ];
+        let mut csv = CSV::new_with_data("test.csv", original.clone());
+        csv.start_journal();
+
+        csv.drop_columns(&["a", "c"]).unwrap();
+        assert_eq!(vec![vec!["b"], vec!["2"], vec![]], csv.data);
+        csv.undo().unwrap();
+        assert_eq!(original, csv.data);
+
+        csv.drop_columns(&["c"]).unwrap();
+        assert_eq!(vec![vec!["a", "b"], vec!["1", "2"], vec!["4"]], csv.data);
+        csv.undo().unwrap();
+
+        csv.reorder_columns(&["c", "a"]).unwrap();
+        assert_eq!(vec![vec!["c", "a", "b"], vec!["3", "1", "2"], vec!["", "4", ""]], csv.data);
+        csv.undo().unwrap();
+        assert_eq!(original, csv.data);
+
+        let replayed = Journal::from_text(&csv.journal().unwrap().to_text()).unwrap();
+        assert_eq!(csv.journal().unwrap().edits(), replayed.edits());
+    }
+
+    #[test]
+    fn test_sort_by_columns() {
+        let mut csv = CSV::new_with_data("test.csv", vec![
+            vec![String::from("name"), String::from("score"), String::from("file"), String::from("joined")],
+            vec![String::from("bob"), String::from("10"), String::from("f10"), String::from("2021-05-01")],
+            vec![String::from("Ann"), String::from("9"), String::from("f9"), String::from("")],
+            vec![String::from("cy"), String::from(""), String::from("f100"), String::from("2020-01-31 08:00")],
+            vec![String::from("al"), String::from("10"), String::from("f1"), String::from("2021-05-01T00:00:01")],
+        ]);
+        let names = |csv: &CSV| csv.data[1..].iter().map(|r| r[0].clone()).collect::<Vec<String>>();
+
+        csv.sort_by_columns(&[SortKey::ascending("name", Compare::Lexical)]).unwrap();
+        assert_eq!(vec!["Ann", "al", "bob", "cy"], names(&csv));
+        assert_eq!(String::from("name"), csv.data[0][0]);
+
+        csv.sort_by_columns(&[SortKey::ascending("name", Compare::CaseInsensitive)]).unwrap();
+        assert_eq!(vec!["al", "Ann", "bob", "cy"], names(&csv));
+
+        let mut score = SortKey::descending("score", Compare::Numeric);
+        csv.sort_by_columns(&[score.clone(), SortKey::ascending("name", Compare::Lexical)]).unwrap();
+        assert_eq!(vec!["al", "bob", "Ann", "cy"], names(&csv));
+        score.nulls = Nulls::First;
+        csv.sort_by_columns(&[score]).unwrap();
+        assert_eq!(vec!["cy", "al", "bob", "Ann"], names(&csv));
+
+        csv.sort_by_columns(&[SortKey::ascending("file", Compare::Natural)]).unwrap();
+        assert_eq!(vec!["al", "Ann", "bob", "cy"], names(&csv));
+
+        csv.sort_by_columns(&[SortKey::descending("joined", Compare::Date)]).unwrap();
+        assert_eq!(vec!["al", "bob", "cy", "Ann"], names(&csv));
+
+        assert!(csv.sort_by_columns(&[SortKey::ascending("height", Compare::Numeric)]).is_err());
+
+        let mut empty = CSV::new("test.csv");
+        empty.sort_by_columns(&[]).unwrap();
+        assert!(empty.is_empty());
+        assert!(empty.sort_by_columns(&[SortKey::ascending("name", Compare::Lexical)]).is_err());
+    }
+
+    #[test]
+    fn test_filter_and_query() {
+        let csv = CSV::new_with_data("test.csv", vec![
+            vec![String::from("name"), String::from("age"), String::from("country")],
+            vec![String::from("ann"), String::from("31"), String::from("NL")],
+            vec![String::from("bob"), String::from("9"), String::from("NL")],
+            vec![String::from("cy"), String::from("45"), String::from("BE")],
+            vec![String::from("dee"), String::from("40")],
+        ]);
+        let names = |csv: &CSV| csv.data[1..].iter().map(|r| r[0].clone()).collect::<Vec<String>>();
+
+        let young = csv.filter(|record| record[1].parse::<u32>().unwrap() < 35);
+        assert_eq!(vec!["ann", "bob"], names(&young));
+        assert_eq!(csv.data[0], young.data[0]);
+        assert_eq!(csv.path, young.path);
+
+        let dutch = csv.query("age > 30 && country == 'NL'").unwrap();
+        assert_eq!(vec!["ann"], names(&dutch));
+        let other = csv.query("is_empty(country) || !(starts_with(name, 'a') || country == \"BE\")").unwrap();
+        assert_eq!(vec!["bob", "dee"], names(&other));
+        assert_eq!(5, csv.len());
+
+        assert!(csv.query("age >").is_err());
+        assert!(csv.query("height > 3").is_err());
+        assert!(CSV::new("test.csv").query("age > 3").is_err());
+    }
+
+    #[test]
+    fn test_join() {
+        let customers = CSV::new_with_data("customers.csv", vec![
+            vec![String::from("id"), String::from("name")],
+            vec![String::from("1"), String::from("ann")],
+            vec![String::from("2"), String::from("bob")],
+        ]);
+        let orders = CSV::new_with_data("orders.csv", vec![
+            vec![String::from("customer_id"), String::from("name")],
+            vec![String::from("2"), String::from("pen")],
+            vec![String::from("3"), String::from("cup")],
+        ]);
+        let on = [("id", "customer_id")];
+
+        let joined = customers.join(&orders, &on, JoinKind::Full).unwrap();
+        assert_eq!(vec!["id", "name_left", "name_right"], joined.data[0]);
+        assert_eq!(vec!["1", "ann", ""], joined.data[1]);
+        assert_eq!(vec!["2", "bob", "pen"], joined.data[2]);
+        assert_eq!(vec!["3", "", "cup"], joined.data[3]);
+        assert_eq!("customers.csv", joined.path);
+
+        let joined = customers.join_with_suffixes(&orders, &on, JoinKind::Inner, ("", "_order")).unwrap();
+        assert_eq!(vec!["id", "name", "name_order"], joined.data[0]);
+        assert_eq!(2, joined.len());
+
+        assert!(customers.join(&orders, &[("id", "missing")], JoinKind::Inner).is_err());
+    }
+
+    #[test]
+    fn test_melt_and_pivot() {
+        let wide = CSV::new_with_data("budget.csv", vec![
+            vec![String::from("dept"), String::from("jan"), String::from("feb")],
+            vec![String::from("ops"), String::from("10"), String::from("12")],
+            vec![String::from("hr"), String::from("4"), String::from("x")],
+        ]);
+
+        let long = wide.melt(&["dept"], &[], "month", "amount").unwrap();
+        assert_eq!(vec!["dept", "month", "amount"], long.data[0]);
+        assert_eq!(vec!["ops", "jan", "10"], long.data[1]);
+        assert_eq!(vec!["ops", "feb", "12"], long.data[2]);
+        assert_eq!(5, long.len());
+        assert_eq!(3, wide.melt(&["dept"], &["feb"], "month", "amount").unwrap().len());
+
+        let back = long.pivot(&["dept"], "month", "amount", Aggregate::First).unwrap();
+        assert_eq!(wide.data, back.csv.data);
+        assert!(back.failures.is_empty());
+
+        let summed = long.pivot(&[], "dept", "amount", Aggregate::Sum).unwrap();
+        assert_eq!(vec!["ops", "hr"], summed.csv.data[0]);
+        assert_eq!(vec!["22", "4"], summed.csv.data[1]);
+        assert_eq!(vec![AggFailure { row: 4, column: String::from("amount"), value: String::from("x") }], summed.failures);
+
+        let mut sparse = long.filter(|record| record[0] != "hr" || record[1] != "feb");
+        sparse.insert_one(vec![String::from("it"), String::from("feb"), String::from("1")]);
+        let wide_again = sparse.pivot(&["dept"], "month", "amount", Aggregate::Count).unwrap();
+        assert_eq!(vec!["hr", "1", ""], wide_again.csv.data[2]);
+        assert_eq!(vec!["it", "", "1"], wide_again.csv.data[3]);
+
+        assert!(wide.melt(&["missing"], &[], "month", "amount").is_err());
+        assert!(long.pivot(&["dept"], "month", "missing", Aggregate::Sum).is_err());
+    }
+
+    #[test]
+    fn test_transpose() {
+        let mut csv = CSV::new_with_data("test.csv", vec![
+            vec![String::from("time"), String::from("a"), String::from("b")],
+            vec![String::from("1"), String::from("0.5")],
+            vec![String::from("2"), String::from("0.7"), String::from("0.9")],
+        ]);
+        csv.start_journal();
+
+        csv.transpose("NA").unwrap();
+        assert_eq!(vec![
+            vec!["time", "1", "2"],
+            vec!["a", "0.5", "0.7"],
+            vec!["b", "NA", "0.9"],
+        ], csv.data);
+        assert!(matches!(csv.check_state(), SaveState::Unsaved));
+
+        assert_eq!(1, csv.journal().unwrap().edits().len());
+        csv.undo().unwrap();
+        assert_eq!(vec!["1", "0.5"], csv.data[1]);
+
+        let mut empty = CSV::new("test.csv");
+        empty.transpose("").unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut csv = CSV::new_with_data("test.csv", vec![
+            vec![String::from("id"), String::from("name")],
+            vec![String::from("1"), String::from("ann")],
+            vec![String::from("2"), String::from("bob")],
+            vec![String::from("1"), String::from("ann")],
+            vec![String::from("3"), String::from("bob")],
+        ]);
+        csv.start_journal();
+
+        assert_eq!(vec![1, 3], csv.duplicates(None).unwrap());
+        assert_eq!(vec![1, 2, 3, 4], csv.duplicates(Some(&["name"])).unwrap());
+
+        assert_eq!(2, csv.dedup(Some(&["name"]), Keep::Last).unwrap());
+        assert_eq!(vec![vec!["id", "name"], vec!["1", "ann"], vec!["3", "bob"]], csv.data);
+        while csv.undo().is_ok() {}
+        assert_eq!(5, csv.len());
+
+        assert_eq!(1, csv.dedup(None, Keep::First).unwrap());
+        assert_eq!(vec!["2", "bob"], csv.data[2]);
+        assert!(csv.duplicates(None).unwrap().is_empty());
+        assert!(csv.dedup(Some(&["missing"]), Keep::First).is_err());
+    }
+
+    #[test]
+    fn test_concat() {
+        let monday = CSV::new_with_data("monday.csv", vec![
+            vec![String::from("id"), String::from("total")],
+            vec![String::from("1"), String::from("10")],
+        ]);
+        let tuesday = CSV::new_with_data("tuesday.csv", vec![
+            vec![String::from("total"), String::from("id"), String::from("note")],
+            vec![String::from("7"), String::from("2"), String::from("late")],
+            vec![String::from("3"), String::from("3")],
+        ]);
+        let days = [monday, tuesday];
+
+        let union = CSV::concat(&days, HeaderPolicy::Union).unwrap();
+        assert_eq!(vec![
+            vec!["id", "total", "note"],
+            vec!["1", "10", ""],
+            vec!["2", "7", "late"],
+            vec!["3", "3", ""],
+        ], union.data);
+        assert_eq!("monday.csv", union.path);
+
+        let shared = CSV::concat(&days, HeaderPolicy::Intersection).unwrap();
+        assert_eq!(vec!["id", "total"], shared.data[0]);
+        assert_eq!(vec!["2", "7"], shared.data[2]);
+
+        assert!(CSV::concat(&days, HeaderPolicy::Strict).is_err());
+        assert!(CSV::concat(&[], HeaderPolicy::Union).is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        let csv = CSV::new_with_data("orders.csv", vec![
+            vec![String::from("customer"), String::from("total")],
+            vec![String::from("acme"), String::from("10")],
+            vec![String::from("zeta"), String::from("200")],
+            vec![String::from("acme"), String::from("3")],
+            vec![String::from("bolt"), String::from("40")],
+            vec![String::from("acme"), String::from("5")],
+        ]);
+
+        let parts = csv.split_by_rows(2).unwrap();
+        assert_eq!(vec![3, 3, 2], parts.iter().map(|part| part.csv.len()).collect::<Vec<usize>>());
+        assert!(parts.iter().all(|part| part.csv.data[0] == csv.data[0]));
+        assert_eq!(vec!["acme", "5"], parts[2].csv.data[1]);
+        assert_eq!(3, parts[2].number);
+
+        // The header takes 15 bytes, the records 8, 9, 7, 8 and 7.
+        let parts = csv.split_by_size(31);
+        assert_eq!(vec![2, 3, 3], parts.iter().map(|part| part.csv.len()).collect::<Vec<usize>>());
+        assert_eq!(5, csv.split_by_size(1).len());
+
+        let parts = csv.partition_by("customer").unwrap();
+        assert_eq!(vec![Some("acme"), Some("zeta"), Some("bolt")], parts.iter().map(|part| part.value.as_deref()).collect::<Vec<_>>());
+        assert_eq!(4, parts[0].csv.len());
+        assert_eq!(vec!["acme", "3"], parts[0].csv.data[2]);
+        assert!(csv.partition_by("missing").is_err());
+
+        assert!(csv.split_by_rows(0).unwrap_err().is::<errors::DataNotFound>());
+        assert!(CSV::new("orders.csv").split_by_rows(2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_journal_undo_redo_replay() {
+        let original: Vec<Record> = vec![
+            vec![String::from("Header 1"), String::from("Header 2")],
+            vec![String::from("Value 1"), String::from("Value 2")],
+        ];
+        let mut csv = CSV::new_with_data("test.csv", original.clone());
+        assert!(csv.undo().is_err());
+
+        csv.start_journal();
+        csv.insert_one(vec![String::from("Value 3"), String::from("Value 4")]);
+        csv.checkpoint("inserted").unwrap();
+        csv.apply(Edit::UpdateCell {
+            row: 1,
+            column: 0,
+            old: String::from("Value 1"),
+            new: String::from("Changed"),
+        }).unwrap();
+        csv.apply(Edit::RemoveColumn {
+            column: 1,
+            values: vec![String::from("Header 2"), String::from("Value 2"), String::from("Value 4")],
+        }).unwrap();
+        let edited = csv.data.clone();
+        assert_eq!(vec![String::from("Changed")], edited[1]);
+
+        csv.undo_to_checkpoint("inserted").unwrap();
+        assert_eq!(3, csv.len());
+        assert_eq!(String::from("Value 1"), csv.data[1][0]);
+
+        csv.redo().unwrap();
+        csv.redo().unwrap();
+        assert!(csv.redo().is_err());
+        assert_eq!(edited, csv.data);
+
+        let journal = Journal::from_text(&csv.journal().unwrap().to_text()).unwrap();
+
+        csv.undo().unwrap();
+        csv.undo().unwrap();
+        csv.undo().unwrap();
+        assert_eq!(original, csv.data);
+        assert!(csv.undo().is_err());
+
+        let mut fresh = CSV::new_with_data("test.csv", original);
+        fresh.replay(&journal).unwrap();
+        assert_eq!(edited, fresh.data);
+        assert!(fresh.replay(&journal).is_err());
+    }
+
 
 }
\ No newline at end of file
@@ -0,0 +1,180 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    ops::Deref,
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{errors, Edit, Result, Transaction, CSV};
+
+/// How a [`LockedCSV`] holds its lock. Any number of processes can hold a
+/// shared lock at once, an exclusive lock is only handed out when nobody else
+/// holds one of either kind.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+/// Sidecar file the lock is taken on, e.g. `data.csv` -> `data.csv.lock`. The
+/// CSV file itself can't be locked because saving replaces it with a new file.
+pub fn lock_path(csv_path: &str) -> String {
+    format!("{}.lock", csv_path)
+}
+
+/// A [`CSV`] read while holding an advisory lock on its file, which is held
+/// until the `LockedCSV` is dropped. Locks are only respected by other
+/// processes that lock the file too, e.g. through `LockedCSV`.
+///
+/// Derefs to the [`CSV`] inside for reading. Changes go through
+/// [apply](LockedCSV::apply) and [transaction](LockedCSV::transaction), so
+/// nothing reaches the file except through the saving methods here, which
+/// require a [`LockMode::Exclusive`] lock and fail with
+/// [`FileLocked`](errors::FileLocked) under a shared one.
+#[derive(Debug)]
+pub struct LockedCSV<'a> {
+    csv: CSV<'a>,
+    mode: LockMode,
+    lock: File,
+}
+
+fn open_lock_file(path: &str) -> Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_path(path))?;
+    Ok(file)
+}
+
+fn locked_error(path: &str) -> Box<dyn std::error::Error> {
+    Box::new(errors::FileLocked {
+        message: format!("{} is locked by someone else", path),
+    })
+}
+
+/// Try to take the lock once, `Ok(false)` when someone else holds it.
+fn try_lock(file: &File, mode: LockMode) -> Result<bool> {
+    let locked = match mode {
+        LockMode::Shared => file.try_lock_shared(),
+        LockMode::Exclusive => file.try_lock(),
+    };
+    match locked {
+        Ok(()) => Ok(true),
+        Err(TryLockError::WouldBlock) => Ok(false),
+        Err(TryLockError::Error(e)) => Err(Box::new(e)),
+    }
+}
+
+impl<'a> LockedCSV<'a> {
+    /// Wait for the lock on `path`, then read the file with
+    /// [CSV::new_from_file].
+    pub fn open(path: &'a str, mode: LockMode) -> Result<LockedCSV<'a>> {
+        let lock = open_lock_file(path)?;
+        match mode {
+            LockMode::Shared => lock.lock_shared()?,
+            LockMode::Exclusive => lock.lock()?,
+        }
+        LockedCSV::read(path, mode, lock)
+    }
+
+    /// Same as [open](LockedCSV::open), but fails right away with
+    /// [`FileLocked`](errors::FileLocked) if the lock is held by someone else.
+    pub fn try_open(path: &'a str, mode: LockMode) -> Result<LockedCSV<'a>> {
+        let lock = open_lock_file(path)?;
+        if !try_lock(&lock, mode)? {
+            return Err(locked_error(path));
+        }
+        LockedCSV::read(path, mode, lock)
+    }
+
+    /// Same as [open](LockedCSV::open), but gives up with
+    /// [`FileLocked`](errors::FileLocked) after waiting for `timeout`.
+    pub fn open_timeout(path: &'a str, mode: LockMode, timeout: Duration) -> Result<LockedCSV<'a>> {
+        let lock = open_lock_file(path)?;
+        let deadline = Instant::now() + timeout;
+
+        while !try_lock(&lock, mode)? {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(locked_error(path));
+            }
+            thread::sleep((deadline - now).min(Duration::from_millis(10)));
+        }
+        LockedCSV::read(path, mode, lock)
+    }
+
+    fn read(path: &'a str, mode: LockMode, lock: File) -> Result<LockedCSV<'a>> {
+        let csv = CSV::new_from_file(path)?;
+        Ok(LockedCSV { csv, mode, lock })
+    }
+
+    /// The kind of lock being held.
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.mode != LockMode::Exclusive {
+            return Err(
+                Box::new(errors::FileLocked {
+                    message: format!("{} is only locked for reading, saving needs an exclusive lock", self.csv.path),
+                })
+            );
+        }
+        Ok(())
+    }
+
+    /// [CSV::apply] to the records in memory, under either kind of lock.
+    pub fn apply(&mut self, edit: Edit) -> Result<()> {
+        self.csv.apply(edit)
+    }
+
+    /// [CSV::transaction] on the records in memory, under either kind of
+    /// lock.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Transaction<'_>) -> Result<T>,
+    {
+        self.csv.transaction(f)
+    }
+
+    /// [CSV::save] while holding an exclusive lock.
+    pub fn save(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.csv.save()
+    }
+
+    /// [CSV::force_save] while holding an exclusive lock.
+    pub fn force_save(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.csv.force_save()
+    }
+
+    /// [CSV::save_custom] while holding an exclusive lock.
+    pub fn save_custom(&mut self, c: char) -> Result<()> {
+        self.check_writable()?;
+        self.csv.save_custom(c)
+    }
+
+    /// [CSV::append_new_records] while holding an exclusive lock.
+    pub fn append_new_records(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.csv.append_new_records()
+    }
+
+    /// Release the lock and hand back the [`CSV`].
+    pub fn unlock(self) -> Result<CSV<'a>> {
+        self.lock.unlock()?;
+        Ok(self.csv)
+    }
+}
+
+impl<'a> Deref for LockedCSV<'a> {
+    type Target = CSV<'a>;
+
+    fn deref(&self) -> &CSV<'a> {
+        &self.csv
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+
+use super::{errors, utils, Record, Result, CSV};
+
+/// A change [`merge3`] couldn't make on its own. The merged CSV holds our
+/// side of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    /// Index of the record in the merged `CSV.data`.
+    pub row: usize,
+    /// Values of the key columns.
+    pub key: Vec<String>,
+    /// The column both sides changed differently, or `None` when one side
+    /// removed the record and the other changed it. The removing side is
+    /// then `None` and the other side holds the record as a CSV line.
+    pub column: Option<String>,
+    pub base: Option<String>,
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+}
+
+/// The result of [`merge3`].
+#[derive(Debug)]
+pub struct Merge<'a> {
+    pub csv: CSV<'a>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// One version of the CSV, its records looked up by key.
+struct Version<'v> {
+    header: &'v Record,
+    records: &'v [Record],
+    rows: HashMap<Vec<String>, usize>,
+}
+
+impl<'v> Version<'v> {
+    fn new(csv: &'v CSV, keys: &[&str]) -> Result<Version<'v>> {
+        let header = csv.data.first().ok_or_else(|| -> Box<dyn std::error::Error> {
+            Box::new(errors::DataNotFound {
+                message: format!("{} has no header to merge", csv.path),
+            })
+        })?;
+        let columns = keys
+            .iter()
+            .map(|name| utils::column_index(&csv.data, name))
+            .collect::<Result<Vec<usize>>>()?;
+
+        let mut rows = HashMap::new();
+        for (i, record) in csv.data.iter().enumerate().skip(1) {
+            let key: Vec<String> = columns.iter().map(|c| record.get(*c).cloned().unwrap_or_default()).collect();
+            if rows.insert(key.clone(), i).is_some() {
+                return Err(
+                    Box::new(errors::DuplicateKey {
+                        message: format!("Key {:?} occurs more than once in {}", key, csv.path),
+                    })
+                );
+            }
+        }
+
+        Ok(Version { header, records: &csv.data, rows })
+    }
+
+    fn record(&self, key: &[String]) -> Option<&'v Record> {
+        self.rows.get(key).map(|i| &self.records[*i])
+    }
+
+    /// The field of `record` in the column called `name`, `None` if this
+    /// version has no such column.
+    fn field<'r>(&self, record: &'r Record, name: &str) -> Option<&'r str> {
+        self.header
+            .iter()
+            .position(|h| h == name)
+            .map(|i| record.get(i).map_or("", |f| f.as_str()))
+    }
+
+    /// Whether `record` has the same fields as `base` in every column of
+    /// `header`, a column missing from either counting as empty.
+    fn unchanged(&self, record: &Record, base: &Version, base_record: &Record, header: &Record) -> bool {
+        header.iter().all(|name| {
+            self.field(record, name).unwrap_or("") == base.field(base_record, name).unwrap_or("")
+        })
+    }
+}
+
+/// Merge the changes `ours` and `theirs` each made to `base`, matching
+/// records by the values of `keys`, which must be unique in each version.
+///
+/// Columns added on either side are kept and columns removed on either side
+/// are dropped. A field changed on one side only takes that change; when
+/// both sides changed it differently, ours is kept and a [`Conflict`] is
+/// reported. Records added on either side are kept, records removed on one
+/// side are dropped unless the other side changed them, which is a conflict
+/// that keeps the changed record. Records keep the order of `ours`, with
+/// those only `theirs` added at the end. The merged CSV has the path of
+/// `ours`.
+///
+/// As a git merge driver: load `%O`, `%A` and `%B`, merge them, save the
+/// result over `%A` and exit with an error when there are conflicts.
+pub fn merge3<'a>(base: &CSV, ours: &CSV<'a>, theirs: &CSV, keys: &[&str]) -> Result<Merge<'a>> {
+    let base = Version::new(base, keys)?;
+    let our = Version::new(ours, keys)?;
+    let their = Version::new(theirs, keys)?;
+
+    let mut header: Record = our.header
+        .iter()
+        .filter(|name| their.header.contains(name) || !base.header.contains(name))
+        .cloned()
+        .collect();
+    header.extend(
+        their.header
+            .iter()
+            .filter(|name| !base.header.contains(name) && !our.header.contains(name))
+            .cloned(),
+    );
+
+    let mut data = vec![header.clone()];
+    let mut conflicts = Vec::new();
+    let key_of = |version: &Version, record: &Record| -> Vec<String> {
+        keys.iter().map(|name| version.field(record, name).unwrap_or("").to_string()).collect()
+    };
+
+    // Our records in our order, then the ones only they have.
+    let candidates = ours.data[1..]
+        .iter()
+        .map(|record| key_of(&our, record))
+        .chain(
+            theirs.data[1..]
+                .iter()
+                .map(|record| key_of(&their, record))
+                .filter(|key| our.record(key).is_none()),
+        );
+
+    for key in candidates {
+        let (b, o, t) = (base.record(&key), our.record(&key), their.record(&key));
+
+        // A side that removed the record: fine if the other side left it
+        // alone, a conflict otherwise.
+        let removed_by = match (b, o, t) {
+            (Some(_), None, None) => continue,
+            (Some(b), None, Some(t)) => Some((t, &their, b, false)),
+            (Some(b), Some(o), None) => Some((o, &our, b, true)),
+            _ => None,
+        };
+        if let Some((kept, side, b, ours_kept)) = removed_by {
+            if side.unchanged(kept, &base, b, &header) {
+                continue;
+            }
+            let line = Some(utils::record_to_quoted_line(kept, ','));
+            conflicts.push(Conflict {
+                row: data.len(),
+                key: key.clone(),
+                column: None,
+                base: Some(utils::record_to_quoted_line(b, ',')),
+                ours: if ours_kept { line.clone() } else { None },
+                theirs: if ours_kept { None } else { line },
+            });
+            data.push(header.iter().map(|name| side.field(kept, name).unwrap_or("").to_string()).collect());
+            continue;
+        }
+
+        let mut merged = Record::with_capacity(header.len());
+        for name in &header {
+            let base_value = b.and_then(|b| base.field(b, name));
+            let our_value = o.and_then(|o| our.field(o, name));
+            let their_value = t.and_then(|t| their.field(t, name));
+
+            let value = if our_value == their_value || their_value == base_value {
+                our_value
+            } else if our_value == base_value {
+                their_value
+            } else {
+                conflicts.push(Conflict {
+                    row: data.len(),
+                    key: key.clone(),
+                    column: Some(name.clone()),
+                    base: base_value.map(String::from),
+                    ours: our_value.map(String::from),
+                    theirs: their_value.map(String::from),
+                });
+                our_value
+            };
+            merged.push(value.unwrap_or("").to_string());
+        }
+        data.push(merged);
+    }
+
+    Ok(Merge { csv: CSV::from_parts(ours.path, data, 0), conflicts })
+}
+
+impl<'a> Merge<'a> {
+    /// The merged CSV with an extra column called `column` describing the
+    /// conflicts in each record, empty for records without any. A field
+    /// conflict reads `name: ours=<value> | theirs=<value>`, a record one
+    /// `removed by ours` or `removed by theirs`; several are joined by `; `.
+    pub fn with_conflict_column(&self, column: &str) -> CSV<'a> {
+        let mut markers = vec![String::new(); self.csv.data.len()];
+        markers[0] = column.to_string();
+
+        for conflict in &self.conflicts {
+            let marker = match &conflict.column {
+                Some(name) => format!(
+                    "{}: ours={} | theirs={}",
+                    name,
+                    conflict.ours.as_deref().unwrap_or(""),
+                    conflict.theirs.as_deref().unwrap_or("")
+                ),
+                None if conflict.ours.is_none() => String::from("removed by ours"),
+                None => String::from("removed by theirs"),
+            };
+            let existing = &mut markers[conflict.row];
+            if !existing.is_empty() {
+                existing.push_str("; ");
+            }
+            existing.push_str(&marker);
+        }
+
+        let data = self.csv.data
+            .iter()
+            .zip(markers)
+            .map(|(record, marker)| {
+                let mut record = record.clone();
+                record.push(marker);
+                record
+            })
+            .collect();
+        CSV::from_parts(self.csv.path, data, 0)
+    }
+}
+
+
+#[cfg(test)]
+mod merge_tests {
+    use super::*;
+
+    fn csv<'p>(path: &'p str, rows: &[&[&str]]) -> CSV<'p> {
+        CSV::new_with_data(path, rows.iter().map(|r| r.iter().map(|f| f.to_string()).collect()).collect())
+    }
+
+    #[test]
+    fn merges_independent_changes() {
+        let base = csv("base.csv", &[&["id", "name", "city", "fax"], &["1", "ann", "Oslo", "x"], &["2", "bob", "Rome", "y"], &["3", "cy", "Nice", "z"]]);
+        let ours = csv("ours.csv", &[&["id", "name", "city", "fax", "email"], &["2", "rob", "Rome", "y", "r@x"], &["1", "ann", "Oslo", "x", ""], &["4", "dee", "Bern", "", ""]]);
+        let theirs = csv("theirs.csv", &[&["id", "name", "city"], &["1", "ann", "Lima"], &["2", "bob", "Rome"], &["3", "cy", "Nice"], &["5", "eve", "Kyiv"]]);
+
+        let merge = merge3(&base, &ours, &theirs, &["id"]).unwrap();
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(vec![
+            vec!["id", "name", "city", "email"],
+            vec!["2", "rob", "Rome", "r@x"],
+            vec!["1", "ann", "Lima", ""],
+            vec!["4", "dee", "Bern", ""],
+            vec!["5", "eve", "Kyiv", ""],
+        ], merge.csv.data);
+        assert_eq!("ours.csv", merge.csv.path);
+    }
+
+    #[test]
+    fn reports_conflicts() {
+        let base = csv("base.csv", &[&["id", "name"], &["1", "ann"], &["2", "bob"]]);
+        let ours = csv("ours.csv", &[&["id", "name"], &["1", "anna"]]);
+        let theirs = csv("theirs.csv", &[&["id", "name"], &["1", "annie"], &["2", "rob"]]);
+
+        let merge = merge3(&base, &ours, &theirs, &["id"]).unwrap();
+        assert_eq!(vec![vec!["id", "name"], vec!["1", "anna"], vec!["2", "rob"]], merge.csv.data);
+        assert_eq!(2, merge.conflicts.len());
+        assert_eq!(Some(String::from("name")), merge.conflicts[0].column);
+        assert_eq!(Some(String::from("annie")), merge.conflicts[0].theirs);
+        assert_eq!(None, merge.conflicts[1].column);
+        assert_eq!(None, merge.conflicts[1].ours);
+        assert_eq!(Some(String::from("2,rob")), merge.conflicts[1].theirs);
+
+        let marked = merge.with_conflict_column("conflict");
+        assert_eq!(vec!["id", "name", "conflict"], marked.data[0]);
+        assert_eq!(vec!["1", "anna", "name: ours=anna | theirs=annie"], marked.data[1]);
+        assert_eq!(vec!["2", "rob", "removed by ours"], marked.data[2]);
+
+        let twice = csv("twice.csv", &[&["id", "name"], &["1", "a"], &["1", "b"]]);
+        assert!(merge3(&base, &twice, &theirs, &["id"]).unwrap_err().is::<errors::DuplicateKey>());
+    }
+}
@@ -0,0 +1,185 @@
+use std::{borrow::Cow, ffi::{c_int, c_long, c_void}, fs::File, os::fd::AsRawFd, str};
+
+use super::{errors, utils, Record, Result};
+
+const PROT_READ: c_int = 1;
+const MAP_PRIVATE: c_int = 2;
+
+// The crate has no dependencies, so these are declared by hand. They match
+// `mmap(2)` and `munmap(2)` in glibc and musl, where `off_t` is a `long`.
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: c_long) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+/// Read only mapping of a whole file. The mapping is private, so changes made
+/// to the file by other processes while it is mapped are not guaranteed to be
+/// visible (or invisible) - don't edit a file while a [`MappedCSV`] holds it.
+struct Mmap {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+// The mapping is read only and never handed out mutably.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    fn open(file: &File) -> Result<Mmap> {
+        let len = usize::try_from(file.metadata()?.len())?;
+
+        // mmap refuses zero length mappings, an empty file is just an empty slice.
+        if len == 0 {
+            return Ok(Mmap { ptr: std::ptr::null_mut(), len });
+        }
+
+        let ptr = unsafe {
+            mmap(std::ptr::null_mut(), len, PROT_READ, MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+        if ptr as isize == -1 {
+            return Err(Box::new(std::io::Error::last_os_error()));
+        }
+
+        Ok(Mmap { ptr, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe {
+                munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+/// Memory mapped, lazily parsed alternative to [`CSV::new_from_file`](crate::CSV::new_from_file)
+/// (Linux only). Opening the file only finds where each record starts and
+/// ends; fields are parsed when a record is asked for, borrowing straight from
+/// the page cache whenever a field needs no unquoting. See
+/// [open](MappedCSV::open) for what the file must not go through meanwhile.
+///
+/// Records are split exactly like [`CSV::new_from_file`](crate::CSV::new_from_file)
+/// does, so `mapped.records()` yields the same data as `CSV.data`.
+pub struct MappedCSV {
+    map: Mmap,
+    lines: Vec<(usize, usize)>,
+}
+
+impl MappedCSV {
+    /// Map the file at `path` into memory and index its records. The file must
+    /// use the ".csv" extension and be valid UTF8. The file is read once, to
+    /// find the records and check them for UTF8 at the same time.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be truncated or written to, by this or any other
+    /// process, until the [`MappedCSV`] and everything borrowed from it are
+    /// dropped. Reading a truncated part of the mapping raises `SIGBUS`, and
+    /// a write can change text that was already checked or handed out as
+    /// `&str`, e.g. into invalid UTF8. Holding a
+    /// [`LockedCSV`](crate::LockedCSV) lock on the file keeps cooperating
+    /// processes from writing it.
+    pub unsafe fn open(path: &str) -> Result<MappedCSV> {
+        if !path.ends_with(".csv") {
+            return Err(
+                Box::new(errors::IncorrectFileType {
+                    message: String::from("You must use \".csv\" file!")
+                })
+            );
+        }
+
+        let file = File::open(path)?;
+        let map = Mmap::open(&file)?;
+
+        let lines = utils::record_spans(map.bytes())?;
+        let has_delimiter = |(start, end): &(usize, usize)| map.bytes()[*start..*end].contains(&b',');
+        if lines.len() < 2 || !lines.iter().any(has_delimiter) {
+            return Err(
+                Box::new(errors::ParseError {
+                    message: String::from("Unable to parse CSV, please check file"),
+                })
+            );
+        }
+
+        Ok(MappedCSV { map, lines })
+    }
+
+    /// Number of records including the header.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Checks to see if the file holds any records.
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// The unparsed text of a record, quotes and all.
+    pub fn raw_record(&self, index: usize) -> Option<&str> {
+        let (start, end) = *self.lines.get(index)?;
+        str::from_utf8(&self.map.bytes()[start..end]).ok()
+    }
+
+    /// Parse the fields of a single record without copying the ones that
+    /// don't need unquoting.
+    pub fn fields(&self, index: usize) -> Option<Vec<Cow<'_, str>>> {
+        self.raw_record(index).map(|line| utils::parse_fields_borrowed(line, ','))
+    }
+
+    /// Parse a single field. Only the record holding it is looked at.
+    pub fn field(&self, index: usize, column: usize) -> Option<Cow<'_, str>> {
+        self.fields(index)?.into_iter().nth(column)
+    }
+
+    /// Parse a single record into an owned [`Record`].
+    pub fn record(&self, index: usize) -> Option<Record> {
+        self.fields(index).map(|fields| fields.into_iter().map(Cow::into_owned).collect())
+    }
+
+    /// Iterate over every record, parsing each one as it is reached.
+    pub fn records(&self) -> impl Iterator<Item = Record> + '_ {
+        (0..self.len()).filter_map(|i| self.record(i))
+    }
+}
+
+
+#[cfg(test)]
+mod mmap_tests {
+    use super::*;
+
+    #[test]
+    fn mapped_csv_matches_new_from_file() {
+        let path = "./tests/test_data/instance_with_quotes.csv";
+        let csv = crate::CSV::new_from_file(path).expect("failed to read test file");
+        let mapped = unsafe { MappedCSV::open(path) }.expect("failed to map test file");
+
+        assert_eq!(csv.len(), mapped.len());
+        assert_eq!(csv.data, mapped.records().collect::<Vec<Record>>());
+        assert_eq!(Some("\"value 4\r\n\",value 5,value 6"), mapped.raw_record(2));
+        assert!(matches!(mapped.field(1, 0), Some(Cow::Borrowed("value 1"))));
+        assert_eq!(None, mapped.record(3));
+    }
+
+    #[test]
+    fn mapped_csv_rejects_invalid_utf8() {
+        let path = "./tests/test_data/mapped_invalid_utf8.csv";
+        std::fs::write(path, b"a,b\nc,\xff\xfe").unwrap();
+        let mapped = unsafe { MappedCSV::open(path) };
+        std::fs::remove_file(path).unwrap_or(());
+        assert!(matches!(mapped, Err(e) if e.is::<str::Utf8Error>()));
+    }
+
+    #[test]
+    fn mapped_csv_wrong_extension() {
+        assert!(unsafe { MappedCSV::open("./tests/test_data/instance_test.txt") }.is_err());
+    }
+}
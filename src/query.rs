@@ -0,0 +1,398 @@
+use std::cmp::Ordering;
+
+use super::{errors, utils, Record, Result};
+
+/// A filter parsed from a small expression language, with its column names
+/// looked up in a header. Built by [CSV::query](crate::CSV::query), or with
+/// [`Query::parse`] to test records one at a time.
+///
+/// An expression compares fields and literals and combines the comparisons
+/// with `&&`, `||`, `!` and parentheses:
+///
+/// - Columns are written by their header name, or between backticks when the
+///   name holds spaces or symbols: `` `first name` ``.
+/// - Literals are numbers (`30`, `-1.5`) or strings in single or double
+///   quotes, with `\` escaping the next character.
+/// - Comparisons are `==`, `!=`, `<`, `<=`, `>` and `>=`. When both sides
+///   read as numbers they are compared as numbers, so `"30" == 30.0`,
+///   otherwise as strings.
+/// - `contains(a, b)`, `starts_with(a, b)` and `ends_with(a, b)` test
+///   strings, `is_empty(a)` is true for empty or missing fields.
+///
+/// `&&` binds tighter than `||`, so `a == 1 || b == 2 && c == 3` reads as
+/// `a == 1 || (b == 2 && c == 3)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, CompareOp, Operand),
+    Contains(Operand, Operand),
+    StartsWith(Operand, Operand),
+    EndsWith(Operand, Operand),
+    IsEmpty(Operand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Column(usize),
+    Literal(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(String),
+    Symbol(&'static str),
+}
+
+impl Query {
+    /// Parse `expr` against `header`. Fails with a
+    /// [ParseError](errors::ParseError) on bad syntax and a
+    /// [ColumnNotFound](errors::ColumnNotFound) on an unknown column.
+    pub fn parse(expr: &str, header: &Record) -> Result<Query> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser { tokens, pos: 0, depth: 0, header };
+        let expr = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(parse_error(format!("Unexpected {} in query", describe(token))));
+        }
+        Ok(Query { expr })
+    }
+
+    /// Checks whether `record` passes the filter. Fields missing from a short
+    /// record count as empty strings.
+    pub fn matches(&self, record: &Record) -> bool {
+        self.expr.eval(record)
+    }
+}
+
+impl Expr {
+    fn eval(&self, record: &Record) -> bool {
+        match self {
+            Expr::Or(a, b) => a.eval(record) || b.eval(record),
+            Expr::And(a, b) => a.eval(record) && b.eval(record),
+            Expr::Not(a) => !a.eval(record),
+            Expr::Compare(a, op, b) => {
+                let ordering = compare(a.value(record), b.value(record));
+                match op {
+                    CompareOp::Eq => ordering == Ordering::Equal,
+                    CompareOp::Ne => ordering != Ordering::Equal,
+                    CompareOp::Lt => ordering == Ordering::Less,
+                    CompareOp::Le => ordering != Ordering::Greater,
+                    CompareOp::Gt => ordering == Ordering::Greater,
+                    CompareOp::Ge => ordering != Ordering::Less,
+                }
+            }
+            Expr::Contains(a, b) => a.value(record).contains(b.value(record)),
+            Expr::StartsWith(a, b) => a.value(record).starts_with(b.value(record)),
+            Expr::EndsWith(a, b) => a.value(record).ends_with(b.value(record)),
+            Expr::IsEmpty(a) => a.value(record).trim().is_empty(),
+        }
+    }
+}
+
+impl Operand {
+    fn value<'r>(&'r self, record: &'r Record) -> &'r str {
+        match self {
+            Operand::Column(column) => record.get(*column).map_or("", |field| field.as_str()),
+            Operand::Literal(value) => value,
+        }
+    }
+}
+
+/// Numbers when both sides read as one, strings otherwise.
+fn compare(a: &str, b: &str) -> Ordering {
+    match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        (Ok(x), Ok(y)) if !x.is_nan() && !y.is_nan() => x.total_cmp(&y),
+        _ => a.cmp(b),
+    }
+}
+
+fn parse_error(message: String) -> Box<dyn std::error::Error> {
+    Box::new(errors::ParseError { message })
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(name) => format!("\"{}\"", name),
+        Token::Str(value) => format!("string '{}'", value),
+        Token::Num(value) => format!("number {}", value),
+        Token::Symbol(symbol) => format!("\"{}\"", symbol),
+    }
+}
+
+const SYMBOLS: [&str; 13] = ["==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "(", ")", ",", "="];
+
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '\'' || c == '"' || c == '`' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(parse_error(format!("Unclosed {} in query", c))),
+                    Some('\\') if c != '`' => {
+                        let escaped = chars.get(i + 1).ok_or_else(|| parse_error(format!("Unclosed {} in query", c)))?;
+                        value.push(*escaped);
+                        i += 2;
+                    }
+                    Some(next) if *next == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(next) => {
+                        value.push(*next);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(if c == '`' { Token::Ident(value) } else { Token::Str(value) });
+        } else if c.is_ascii_digit() || c == '.' || (c == '-' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            if number.parse::<f64>().is_err() {
+                return Err(parse_error(format!("\"{}\" is not a number", number)));
+            }
+            tokens.push(Token::Num(number));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(**symbol))
+                .ok_or_else(|| parse_error(format!("Unexpected \"{}\" in query", c)))?;
+            if *symbol == "=" {
+                return Err(parse_error(String::from("Use \"==\" to compare in a query")));
+            }
+            tokens.push(Token::Symbol(symbol));
+            i += symbol.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// How deep `!` and parentheses may nest before parsing gives up, so a
+/// hostile query can't overflow the stack.
+const MAX_DEPTH: usize = 256;
+
+/// Recursive descent over the tokens, one method per precedence level.
+struct Parser<'p> {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+    header: &'p Record,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self.tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| parse_error(String::from("Query ends too early")))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, symbol: &'static str) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        match self.next()? {
+            Token::Symbol(found) if found == symbol => Ok(()),
+            token => Err(parse_error(format!("Expected \"{}\", found {} in query", symbol, describe(&token)))),
+        }
+    }
+
+    /// Runs `parse` one nesting level deeper, failing past [MAX_DEPTH].
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= MAX_DEPTH {
+            return Err(parse_error(format!("Query nests deeper than {} levels", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.not()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.nested(Self::not)?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        if self.eat("(") {
+            let expr = self.nested(Self::or)?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+
+        if let (Some(Token::Ident(name)), Some(Token::Symbol("("))) = (self.peek(), self.tokens.get(self.pos + 1)) {
+            let name = name.clone();
+            self.pos += 2;
+            let first = self.operand()?;
+            let expr = match name.as_str() {
+                "is_empty" => Expr::IsEmpty(first),
+                "contains" | "starts_with" | "ends_with" => {
+                    self.expect(",")?;
+                    let second = self.operand()?;
+                    match name.as_str() {
+                        "contains" => Expr::Contains(first, second),
+                        "starts_with" => Expr::StartsWith(first, second),
+                        _ => Expr::EndsWith(first, second),
+                    }
+                }
+                _ => return Err(parse_error(format!("Unknown function \"{}\" in query", name))),
+            };
+            self.expect(")")?;
+            return Ok(expr);
+        }
+
+        let left = self.operand()?;
+        let op = match self.next()? {
+            Token::Symbol("==") => CompareOp::Eq,
+            Token::Symbol("!=") => CompareOp::Ne,
+            Token::Symbol("<") => CompareOp::Lt,
+            Token::Symbol("<=") => CompareOp::Le,
+            Token::Symbol(">") => CompareOp::Gt,
+            Token::Symbol(">=") => CompareOp::Ge,
+            token => return Err(parse_error(format!("Expected a comparison, found {} in query", describe(&token)))),
+        };
+        let right = self.operand()?;
+        Ok(Expr::Compare(left, op, right))
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        match self.next()? {
+            Token::Ident(name) => {
+                let column = utils::column_index(std::slice::from_ref(self.header), &name)?;
+                Ok(Operand::Column(column))
+            }
+            Token::Str(value) | Token::Num(value) => Ok(Operand::Literal(value)),
+            token => Err(parse_error(format!("Expected a column or value, found {} in query", describe(&token)))),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod query_tests {
+    use super::*;
+
+    fn header() -> Record {
+        vec!["name".to_string(), "age".to_string(), "country".to_string(), "first name".to_string()]
+    }
+
+    fn record(fields: &[&str]) -> Record {
+        fields.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn comparisons_and_logic() {
+        let query = Query::parse("age > 30 && country == 'NL'", &header()).unwrap();
+        assert!(query.matches(&record(&["a", "31", "NL", ""])));
+        assert!(!query.matches(&record(&["a", "30", "NL", ""])));
+        assert!(!query.matches(&record(&["a", "45", "BE", ""])));
+
+        // Numbers compare by value, not as strings.
+        let query = Query::parse("age >= 9.0 || !(name != \"x\")", &header()).unwrap();
+        assert!(query.matches(&record(&["a", "10", "", ""])));
+        assert!(query.matches(&record(&["x", "1", "", ""])));
+        assert!(!query.matches(&record(&["a", "1", "", ""])));
+
+        let query = Query::parse("name == 'a' || name == 'b' && age == 1", &header()).unwrap();
+        assert!(query.matches(&record(&["a", "2", "", ""])));
+        assert!(!query.matches(&record(&["b", "2", "", ""])));
+    }
+
+    #[test]
+    fn string_functions() {
+        let query = Query::parse("contains(name, 'an') && starts_with(`first name`, 'J')", &header()).unwrap();
+        assert!(query.matches(&record(&["Jan", "1", "", "Jo"])));
+        assert!(!query.matches(&record(&["Jan", "1", "", "Ko"])));
+
+        let query = Query::parse("is_empty(country) && ends_with(name, 'it\\'s')", &header()).unwrap();
+        assert!(query.matches(&record(&["it's", "1"])));
+        assert!(!query.matches(&record(&["it's", "1", "NL"])));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for bad in ["age >", "age = 3", "(age > 3", "age > 3 age", "lower(name)", "'a", "age > 3 &&"] {
+            assert!(Query::parse(bad, &header()).unwrap_err().is::<errors::ParseError>(), "{}", bad);
+        }
+        assert!(Query::parse("height > 3", &header()).unwrap_err().is::<errors::ColumnNotFound>());
+    }
+
+    #[test]
+    fn nesting_depth_is_limited() {
+        let deep = format!("{}age == 1", "!".repeat(200_000));
+        assert!(Query::parse(&deep, &header()).unwrap_err().is::<errors::ParseError>());
+        let deep = format!("{}age == 1{}", "(".repeat(200_000), ")".repeat(200_000));
+        assert!(Query::parse(&deep, &header()).unwrap_err().is::<errors::ParseError>());
+
+        let query = Query::parse(&format!("{}age == 1", "!".repeat(MAX_DEPTH)), &header()).unwrap();
+        assert!(query.matches(&record(&["a", "1"])));
+        assert!(Query::parse(&format!("{}age == 1", "!".repeat(MAX_DEPTH + 1)), &header()).is_err());
+    }
+}
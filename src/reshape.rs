@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use super::{errors, group::{AggFailure, Aggregate, Failures, State}, utils, Record, Result};
+
+/// Wide to long, see [CSV::melt](crate::CSV::melt).
+pub fn melt(data: &[Record], id_columns: &[&str], value_columns: &[&str], var_name: &str, value_name: &str) -> Result<Vec<Record>> {
+    if data.is_empty() {
+        return Err(
+            Box::new(errors::DataNotFound {
+                message: String::from("CSV has no header to melt"),
+            })
+        );
+    }
+    let ids = id_columns
+        .iter()
+        .map(|name| utils::column_index(data, name))
+        .collect::<Result<Vec<usize>>>()?;
+    let values = if value_columns.is_empty() {
+        (0..data[0].len()).filter(|i| !ids.contains(i)).collect()
+    } else {
+        value_columns
+            .iter()
+            .map(|name| utils::column_index(data, name))
+            .collect::<Result<Vec<usize>>>()?
+    };
+
+    let mut header: Record = ids.iter().map(|i| data[0][*i].clone()).collect();
+    header.push(var_name.to_string());
+    header.push(value_name.to_string());
+
+    let mut melted = Vec::with_capacity(1 + (data.len() - 1) * values.len());
+    melted.push(header);
+    for record in &data[1..] {
+        let id_fields: Record = ids.iter().map(|i| record.get(*i).cloned().unwrap_or_default()).collect();
+        for column in &values {
+            let mut long = id_fields.clone();
+            long.push(data[0][*column].clone());
+            long.push(record.get(*column).cloned().unwrap_or_default());
+            melted.push(long);
+        }
+    }
+
+    Ok(melted)
+}
+
+/// Long to wide, see [CSV::pivot](crate::CSV::pivot).
+pub fn pivot(data: &[Record], index: &[&str], columns: &str, values: &str, aggregate: Aggregate) -> Result<(Vec<Record>, Vec<AggFailure>)> {
+    let index = index
+        .iter()
+        .map(|name| utils::column_index(data, name))
+        .collect::<Result<Vec<usize>>>()?;
+    let columns = utils::column_index(data, columns)?;
+    let values = utils::column_index(data, values)?;
+
+    let mut rows: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut keys: Vec<Vec<String>> = Vec::new();
+    let mut names: HashMap<String, usize> = HashMap::new();
+    let mut header: Record = index.iter().map(|i| data[0][*i].clone()).collect();
+    let mut cells: HashMap<(usize, usize), State> = HashMap::new();
+    let mut failures = Failures::default();
+
+    for (row, record) in data.iter().enumerate().skip(1) {
+        let key: Vec<String> = index.iter().map(|i| record.get(*i).cloned().unwrap_or_default()).collect();
+        let wide_row = *rows.entry(key.clone()).or_insert_with(|| {
+            keys.push(key);
+            keys.len() - 1
+        });
+        let name = record.get(columns).cloned().unwrap_or_default();
+        let wide_column = *names.entry(name.clone()).or_insert_with(|| {
+            header.push(name);
+            header.len() - 1
+        });
+
+        let field = record.get(values).map_or("", |f| f.as_str());
+        if !cells.entry((wide_row, wide_column)).or_insert_with(|| State::new(aggregate)).add(field) {
+            failures.report(data, row, values, field);
+        }
+    }
+
+    let mut wide = Vec::with_capacity(keys.len() + 1);
+    let width = header.len();
+    wide.push(header);
+    for (i, mut record) in keys.into_iter().enumerate() {
+        for column in record.len()..width {
+            record.push(cells.get(&(i, column)).map_or_else(String::new, State::result));
+        }
+        wide.push(record);
+    }
+
+    Ok((wide, failures.list))
+}
@@ -0,0 +1,267 @@
+use std::cmp::Ordering;
+
+use super::{utils, Record, Result};
+
+/// Direction of a [`SortKey`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+/// How the fields of a [`SortKey`] column are compared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compare {
+    /// Plain string comparison, byte by byte.
+    Lexical,
+    /// Case-insensitive string comparison.
+    CaseInsensitive,
+    /// Fields parsed as `f64`, e.g. `"9" < "10"`.
+    Numeric,
+    /// Strings with runs of digits compared by their value, e.g.
+    /// `"file9" < "file10"`.
+    Natural,
+    /// ISO 8601 dates and date-times such as `2024-03-01`,
+    /// `2024-03-01 13:45:00` or `2024-03-01T13:45:00.250Z`.
+    Date,
+}
+
+/// Where fields without a value end up, whatever the [`SortOrder`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+/// One column to sort by. Fields that are empty, missing from a short record,
+/// or can't be read as the [`Compare`] type count as nulls.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub column: String,
+    pub order: SortOrder,
+    pub compare: Compare,
+    pub nulls: Nulls,
+}
+
+impl SortKey {
+    /// Sort `column` ascending, nulls last.
+    pub fn ascending(column: &str, compare: Compare) -> SortKey {
+        SortKey { column: column.to_string(), order: SortOrder::Ascending, compare, nulls: Nulls::Last }
+    }
+
+    /// Sort `column` descending, nulls last.
+    pub fn descending(column: &str, compare: Compare) -> SortKey {
+        SortKey { column: column.to_string(), order: SortOrder::Descending, compare, nulls: Nulls::Last }
+    }
+}
+
+/// A [`SortKey`] with its column looked up in a header.
+#[derive(Debug, Clone)]
+pub struct ResolvedKey {
+    column: usize,
+    key: SortKey,
+}
+
+/// Look up the column of every key in the header of `data`.
+pub fn resolve_keys(data: &[Record], keys: &[SortKey]) -> Result<Vec<ResolvedKey>> {
+    keys.iter()
+        .map(|key| {
+            Ok(ResolvedKey {
+                column: utils::column_index(data, &key.column)?,
+                key: key.clone(),
+            })
+        })
+        .collect()
+}
+
+/// A field read the way its [`Compare`] type wants it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortValue {
+    Null,
+    Text(String),
+    Number(f64),
+    Date([u32; 7]),
+}
+
+fn sort_value(field: Option<&String>, compare: Compare) -> SortValue {
+    let field = match field {
+        Some(field) if !field.trim().is_empty() => field,
+        _ => return SortValue::Null,
+    };
+
+    match compare {
+        Compare::Lexical | Compare::Natural => SortValue::Text(field.clone()),
+        Compare::CaseInsensitive => SortValue::Text(field.to_lowercase()),
+        Compare::Numeric => match field.trim().parse::<f64>() {
+            Ok(number) if !number.is_nan() => SortValue::Number(number),
+            _ => SortValue::Null,
+        },
+        Compare::Date => parse_date(field.trim()).map_or(SortValue::Null, SortValue::Date),
+    }
+}
+
+/// Parses `YYYY-MM-DD` with an optional `HH:MM[:SS[.fraction]]` time after a
+/// space or `T`, and an optional trailing `Z`. Returns the parts in order,
+/// the fraction in nanoseconds.
+fn parse_date(value: &str) -> Option<[u32; 7]> {
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    let (date, time) = match value.find([' ', 'T']) {
+        Some(i) => (&value[..i], Some(&value[i + 1..])),
+        None => (value, None),
+    };
+
+    let mut parts = [0u32; 7];
+    let date: Vec<&str> = date.split('-').collect();
+    if date.len() != 3 || date[0].len() != 4 || date[1].len() != 2 || date[2].len() != 2 {
+        return None;
+    }
+    for (i, part) in date.iter().enumerate() {
+        parts[i] = part.parse().ok()?;
+    }
+    if !(1..=12).contains(&parts[1]) || !(1..=31).contains(&parts[2]) {
+        return None;
+    }
+
+    if let Some(time) = time {
+        let (clock, fraction) = match time.split_once('.') {
+            Some((clock, fraction)) => (clock, Some(fraction)),
+            None => (time, None),
+        };
+        let clock: Vec<&str> = clock.split(':').collect();
+        if clock.len() < 2 || clock.len() > 3 || clock.iter().any(|part| part.len() != 2) {
+            return None;
+        }
+        for (i, part) in clock.iter().enumerate() {
+            parts[3 + i] = part.parse().ok()?;
+        }
+        if parts[3] > 23 || parts[4] > 59 || parts[5] > 60 {
+            return None;
+        }
+        if let Some(fraction) = fraction {
+            if fraction.is_empty() || fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            parts[6] = format!("{:0<9}", fraction).parse().ok()?;
+        }
+    }
+
+    Some(parts)
+}
+
+/// Compares strings treating runs of ASCII digits as numbers.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.iter().take_while(|c| c.is_ascii_digit()).count();
+                let b_len = b.iter().take_while(|c| c.is_ascii_digit()).count();
+                let a_digits = trim_zeros(&a[..a_len]);
+                let b_digits = trim_zeros(&b[..b_len]);
+
+                let ordering = a_digits.len()
+                    .cmp(&b_digits.len())
+                    .then_with(|| a_digits.cmp(b_digits))
+                    .then_with(|| a_len.cmp(&b_len));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a = &a[a_len..];
+                b = &b[b_len..];
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+}
+
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|c| **c == b'0').count();
+    &digits[zeros..]
+}
+
+fn compare_values(a: &SortValue, b: &SortValue, key: &SortKey) -> Ordering {
+    let ordering = match (a, b) {
+        (SortValue::Null, SortValue::Null) => return Ordering::Equal,
+        (SortValue::Null, _) => {
+            return if key.nulls == Nulls::First { Ordering::Less } else { Ordering::Greater };
+        }
+        (_, SortValue::Null) => {
+            return if key.nulls == Nulls::First { Ordering::Greater } else { Ordering::Less };
+        }
+        (SortValue::Text(a), SortValue::Text(b)) if key.compare == Compare::Natural => natural_cmp(a, b),
+        (SortValue::Text(a), SortValue::Text(b)) => a.cmp(b),
+        (SortValue::Number(a), SortValue::Number(b)) => a.total_cmp(b),
+        (SortValue::Date(a), SortValue::Date(b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    };
+
+    match key.order {
+        SortOrder::Ascending => ordering,
+        SortOrder::Descending => ordering.reverse(),
+    }
+}
+
+/// The fields of `record` that `keys` sort by, read once so comparisons
+/// don't have to parse them again.
+pub fn record_values(record: &Record, keys: &[ResolvedKey]) -> Vec<SortValue> {
+    keys.iter()
+        .map(|resolved| sort_value(record.get(resolved.column), resolved.key.compare))
+        .collect()
+}
+
+/// Compares the [record_values] of two records, the first key that tells
+/// them apart wins.
+pub fn compare_record_values(a: &[SortValue], b: &[SortValue], keys: &[ResolvedKey]) -> Ordering {
+    for (i, resolved) in keys.iter().enumerate() {
+        let ordering = compare_values(&a[i], &b[i], &resolved.key);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Indices of `records` in sorted order. The sort is stable. Fields are read
+/// only once per record, not on every comparison.
+pub fn sorted_order(records: &[Record], keys: &[ResolvedKey]) -> Vec<usize> {
+    let values: Vec<Vec<SortValue>> = records.iter().map(|record| record_values(record, keys)).collect();
+
+    let mut order: Vec<usize> = (0..records.len()).collect();
+    order.sort_by(|a, b| compare_record_values(&values[*a], &values[*b], keys));
+    order
+}
+
+
+#[cfg(test)]
+mod sort_tests {
+    use super::*;
+
+    #[test]
+    fn natural_ordering() {
+        assert_eq!(Ordering::Less, natural_cmp("file9", "file10"));
+        assert_eq!(Ordering::Less, natural_cmp("a2b3", "a2b12"));
+        assert_eq!(Ordering::Less, natural_cmp("x7", "x07"));
+        assert_eq!(Ordering::Greater, natural_cmp("b1", "a100"));
+        assert_eq!(Ordering::Equal, natural_cmp("same1", "same1"));
+    }
+
+    #[test]
+    fn date_parsing() {
+        assert_eq!(Some([2024, 3, 1, 0, 0, 0, 0]), parse_date("2024-03-01"));
+        assert_eq!(Some([2024, 3, 1, 13, 45, 0, 0]), parse_date("2024-03-01 13:45"));
+        assert_eq!(Some([2024, 3, 1, 13, 45, 10, 250_000_000]), parse_date("2024-03-01T13:45:10.25Z"));
+        assert_eq!(None, parse_date("01/03/2024"));
+        assert_eq!(None, parse_date("2024-13-01"));
+        assert_eq!(None, parse_date("2024-03-01 25:00"));
+    }
+}
@@ -0,0 +1,113 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use super::{errors, utils::{self, RecordWriter}, Record, Result, CSV};
+
+/// One of the CSVs a CSV was split into, see
+/// [CSV::split_by_rows](crate::CSV::split_by_rows),
+/// [CSV::split_by_size](crate::CSV::split_by_size) and
+/// [CSV::partition_by](crate::CSV::partition_by). Every part starts with the
+/// header and has the path of the CSV it came from.
+#[derive(Debug)]
+pub struct Part<'a> {
+    /// Position of the part, counting from 1.
+    pub number: usize,
+    /// The field all records of the part share, for
+    /// [CSV::partition_by](crate::CSV::partition_by).
+    pub value: Option<String>,
+    pub csv: CSV<'a>,
+}
+
+impl<'a> Part<'a> {
+    pub(crate) fn new(number: usize, value: Option<String>, header: &Record, records: Vec<Record>, path: &'a str) -> Part<'a> {
+        let mut data = Vec::with_capacity(records.len() + 1);
+        data.push(header.clone());
+        data.extend(records);
+        Part { number, value, csv: CSV::from_parts(path, data, 0) }
+    }
+
+    /// The file name `template` gives this part. `{stem}` is replaced by the
+    /// name of the CSV's file without its extension, `{n}` by the part number
+    /// and `{value}` by the partition value. Characters in the value that
+    /// don't belong in a file name become `_`, an empty value is `empty`.
+    pub fn file_name(&self, template: &str) -> String {
+        let stem = Path::new(self.csv.path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("csv");
+        let value = match self.value.as_deref() {
+            None => String::new(),
+            Some("") => String::from("empty"),
+            Some(value) => value
+                .chars()
+                .map(|c| if c.is_alphanumeric() || c == '-' || c == '.' || c == '_' { c } else { '_' })
+                .collect(),
+        };
+
+        template
+            .replace("{stem}", stem)
+            .replace("{n}", &self.number.to_string())
+            .replace("{value}", &value)
+    }
+}
+
+/// Size of `record` as [`write_parts`] writes it, its `\n` included.
+pub fn written_len(record: &Record) -> usize {
+    utils::record_to_quoted_line(record, ',').len() + 1
+}
+
+/// Write every part to a file in `dir`, named by [Part::file_name] with
+/// `template`, e.g. `{stem}_{n}.csv` or `{stem}_{value}.csv`. `dir` is
+/// created if it doesn't exist and existing files are replaced. Fails
+/// before writing anything if two parts would get the same file name or a
+/// name doesn't end in `.csv`. Fields holding a `,` or a newline are written
+/// surrounded by quotes. Returns the paths written, in the order of `parts`.
+pub fn write_parts(parts: &[Part], dir: &str, template: &str) -> Result<Vec<String>> {
+    let mut names: HashMap<String, usize> = HashMap::new();
+    let mut paths = Vec::with_capacity(parts.len());
+    for part in parts {
+        let name = part.file_name(template);
+        if !name.ends_with(".csv") {
+            return Err(
+                Box::new(errors::IncorrectFileType {
+                    message: String::from("You must use \".csv\" file!")
+                })
+            );
+        }
+        if let Some(other) = names.insert(name.clone(), part.number) {
+            return Err(
+                Box::new(errors::DuplicateKey {
+                    message: format!("Parts {} and {} would both be written to {}", other, part.number, name),
+                })
+            );
+        }
+        paths.push(Path::new(dir).join(name).to_string_lossy().into_owned());
+    }
+
+    fs::create_dir_all(dir)?;
+    for (part, path) in parts.iter().zip(&paths) {
+        let mut writer = RecordWriter::new(path)?;
+        for record in &part.csv.data {
+            writer.write(record)?;
+        }
+        writer.finish()?;
+    }
+
+    Ok(paths)
+}
+
+
+#[cfg(test)]
+mod split_tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        let header = vec![String::from("id")];
+        let part = Part::new(3, Some(String::from("Acme / EU")), &header, Vec::new(), "exports/daily.csv");
+        assert_eq!("daily_3.csv", part.file_name("{stem}_{n}.csv"));
+        assert_eq!("daily_Acme___EU.csv", part.file_name("{stem}_{value}.csv"));
+
+        let part = Part::new(1, Some(String::new()), &header, Vec::new(), "daily.csv");
+        assert_eq!("empty.csv", part.file_name("{value}.csv"));
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::Hasher,
+    io,
+    time::SystemTime,
+};
+
+use super::Result;
+
+/// What a file on disk looked like the last time a [`CSV`](crate::CSV) read
+/// or wrote it: its size, modification time and a hash of its contents.
+#[derive(Debug, Clone)]
+pub struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+    hasher: DefaultHasher,
+}
+
+impl FileStamp {
+    /// Read the size and modification time of `path`. Call this *before*
+    /// reading the file, so a change made while reading is caught later.
+    pub fn metadata(path: &str) -> Result<(u64, Option<SystemTime>)> {
+        let meta = fs::metadata(path)?;
+        Ok((meta.len(), meta.modified().ok()))
+    }
+
+    pub fn new(metadata: (u64, Option<SystemTime>), contents: &[u8]) -> FileStamp {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(contents);
+        FileStamp { len: metadata.0, modified: metadata.1, hasher }
+    }
+
+    /// Account for `appended` bytes written to the end of the file.
+    pub fn extend(&mut self, path: &str, appended: &[u8]) -> Result<()> {
+        (self.len, self.modified) = FileStamp::metadata(path)?;
+        self.hasher.write(appended);
+        Ok(())
+    }
+
+    /// Checks if the file at `path` changed since the stamp was taken. The
+    /// contents are only read and hashed when the size or modification time
+    /// differ. A file that no longer exists has nothing to lose, so it does
+    /// not count as changed.
+    pub fn changed(&self, path: &str) -> Result<bool> {
+        let metadata = match FileStamp::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) => match e.downcast_ref::<io::Error>() {
+                Some(io_err) if io_err.kind() == io::ErrorKind::NotFound => return Ok(false),
+                _ => return Err(e),
+            },
+        };
+
+        if metadata == (self.len, self.modified) {
+            return Ok(false);
+        }
+
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&fs::read(path)?);
+        Ok(hasher.finish() != self.hasher.finish())
+    }
+}
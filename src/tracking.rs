@@ -0,0 +1,140 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeSet},
+    hash::{Hash, Hasher},
+};
+
+use super::Record;
+
+/// Keeps count of the changes made to a [`CSV`](crate::CSV) through its methods
+/// and which records they touched since the last save. A hash of the saved
+/// records catches changes made to `CSV.data` directly.
+#[derive(Debug, Default)]
+pub struct ChangeTracker {
+    modifications: u64,
+    rows: BTreeSet<usize>,
+    all_rows: bool,
+    saved_hash: u64,
+}
+
+fn hash_records(records: &[Record]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    records.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl ChangeTracker {
+    /// Record a change to a single record.
+    pub fn row(&mut self, row: usize) {
+        self.modifications += 1;
+        self.rows.insert(row);
+    }
+
+    /// Record a change to every record in `rows`, counted as one modification.
+    pub fn rows(&mut self, rows: std::ops::Range<usize>) {
+        self.modifications += 1;
+        self.rows.extend(rows);
+    }
+
+    /// Record `count` records inserted at `row`, moving the rows after them.
+    pub fn inserted(&mut self, row: usize, count: usize) {
+        self.modifications += 1;
+        self.rows = self.rows.iter().map(|r| if *r >= row { r + count } else { *r }).collect();
+        self.rows.extend(row..row + count);
+    }
+
+    /// Record `count` records removed at `row`, moving the rows after them.
+    /// If any of them had been saved before, the file no longer matches row
+    /// for row and only a full save brings it back in line.
+    pub fn removed(&mut self, row: usize, count: usize, were_saved: bool) {
+        self.modifications += 1;
+        self.rows = self.rows
+            .iter()
+            .filter(|r| **r < row || **r >= row + count)
+            .map(|r| if *r >= row + count { r - count } else { *r })
+            .collect();
+        if were_saved {
+            self.all_rows = true;
+        }
+    }
+
+    /// Record `removed` records at `row` replaced by `inserted` new ones,
+    /// counted as one modification.
+    pub fn replaced(&mut self, row: usize, removed: usize, inserted: usize, were_saved: bool) {
+        self.removed(row, removed, were_saved);
+        self.inserted(row, inserted);
+        self.modifications -= 1;
+    }
+
+    /// Record a change that could have touched any record.
+    pub fn everything(&mut self) {
+        self.modifications += 1;
+        self.all_rows = true;
+    }
+
+    pub fn modifications(&self) -> u64 {
+        self.modifications
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.rows.is_empty() && !self.all_rows
+    }
+
+    /// Rows changed since the last save, or `None` when any row could have
+    /// changed.
+    pub fn changed_rows(&self) -> Option<Vec<usize>> {
+        if self.all_rows {
+            return None;
+        }
+        Some(self.rows.iter().copied().collect())
+    }
+
+    /// Forget every change, `records` are on disk.
+    pub fn saved(&mut self, records: &[Record]) {
+        self.rows.clear();
+        self.all_rows = false;
+        self.saved_hash = hash_records(records);
+    }
+
+    /// Checks `records` for changes that were not tracked, given that the
+    /// first `saved_len` of them were on disk when [saved](ChangeTracker::saved)
+    /// was last called and any after those must be tracked rows. Once a
+    /// tracked change touched a saved record it is unsaved anyway, so the
+    /// saved records are no longer compared.
+    pub fn untracked(&self, records: &[Record], saved_len: usize) -> bool {
+        self.saved_records_changed(records, saved_len) || self.untracked_new_rows(records, saved_len)
+    }
+
+    /// Record the changes [untracked](ChangeTracker::untracked) finds: any
+    /// record could have changed if a saved one did, otherwise only the new
+    /// records did. Returns whether there were any.
+    pub fn track_untracked(&mut self, records: &[Record], saved_len: usize) -> bool {
+        if self.saved_records_changed(records, saved_len) {
+            self.everything();
+        } else if self.untracked_new_rows(records, saved_len) {
+            self.rows(saved_len..records.len());
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn saved_records_changed(&self, records: &[Record], saved_len: usize) -> bool {
+        if self.all_rows || self.rows.range(..saved_len).next().is_some() {
+            return false;
+        }
+        records.len() < saved_len || hash_records(&records[..saved_len]) != self.saved_hash
+    }
+
+    fn untracked_new_rows(&self, records: &[Record], saved_len: usize) -> bool {
+        (saved_len..records.len()).any(|row| !self.rows.contains(&row))
+    }
+
+    /// Forget the changes to rows from `row` onwards, only those got written
+    /// after `records`.
+    pub fn saved_from(&mut self, row: usize, records: &[Record]) {
+        self.rows.retain(|r| *r < row);
+        if self.is_clean() {
+            self.saved_hash = hash_records(records);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use super::{errors, utils, Edit, Record, Result};
+
+/// Staged changes inside [CSV::transaction](crate::CSV::transaction). Every
+/// change is checked and applied right away, so later steps of the
+/// transaction see the earlier ones, but they are only kept if the whole
+/// transaction succeeds. A transaction dropped without being committed, e.g.
+/// when the closure panics, takes its changes back.
+#[derive(Debug)]
+pub struct Transaction<'t> {
+    data: &'t mut Vec<Record>,
+    edits: Vec<Edit>,
+}
+
+impl<'t> Transaction<'t> {
+    pub(crate) fn new(data: &'t mut Vec<Record>) -> Transaction<'t> {
+        Transaction { data, edits: Vec::new() }
+    }
+
+    /// The records as they are within the transaction.
+    pub fn data(&self) -> &[Record] {
+        self.data
+    }
+
+    /// Number of records within the transaction, including the header.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Checks to see if there are any records within the transaction.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// A single record within the transaction.
+    pub fn get(&self, row: usize) -> Option<&Record> {
+        self.data.get(row)
+    }
+
+    /// Stage an [`Edit`].
+    pub fn apply(&mut self, edit: Edit) -> Result<()> {
+        edit.apply(self.data)?;
+        self.edits.push(edit);
+        Ok(())
+    }
+
+    /// Stage a record added to the end.
+    pub fn insert_one(&mut self, record: Record) -> Result<()> {
+        self.insert_at(self.data.len(), record)
+    }
+
+    /// Stage a record inserted at `row`, moving the records after it. Row 0
+    /// is the header, the same as for [CSV::insert_at](crate::CSV::insert_at).
+    pub fn insert_at(&mut self, row: usize, record: Record) -> Result<()> {
+        if row == 0 && !self.data.is_empty() {
+            return Err(header_row());
+        }
+        self.apply(Edit::InsertRecords { row, records: vec![record] })
+    }
+
+    /// Stage the removal of the record at `row`, which can't be the header.
+    pub fn remove(&mut self, row: usize) -> Result<()> {
+        if row == 0 {
+            return Err(header_row());
+        }
+        let record = self.data.get(row).cloned().ok_or_else(|| -> Box<dyn std::error::Error> {
+            Box::new(errors::DataNotFound {
+                message: format!("No record {}, CSV has {} records", row, self.data.len()),
+            })
+        })?;
+        self.apply(Edit::RemoveRecords { row, records: vec![record] })
+    }
+
+    /// Stage a new value for the field at `row` in the column named `column`.
+    /// Row 0 is the header and can't be changed here.
+    pub fn update_cell(&mut self, row: usize, column: &str, value: &str) -> Result<()> {
+        if row == 0 {
+            return Err(header_cell());
+        }
+        let column = utils::column_index(self.data, column)?;
+        let old = self.data
+            .get(row)
+            .and_then(|record| record.get(column))
+            .cloned()
+            .ok_or_else(|| -> Box<dyn std::error::Error> {
+                Box::new(errors::DataNotFound {
+                    message: format!("No field at record {}, column {}", row, column),
+                })
+            })?;
+        self.apply(Edit::UpdateCell { row, column, old, new: value.to_string() })
+    }
+
+    /// Take back every staged edit, newest first.
+    pub(crate) fn rollback(mut self) -> Result<()> {
+        self.take_back()
+    }
+
+    /// The staged edits, which are kept from now on.
+    pub(crate) fn into_edits(mut self) -> Vec<Edit> {
+        std::mem::take(&mut self.edits)
+    }
+
+    fn take_back(&mut self) -> Result<()> {
+        // Nothing else can touch the data during a transaction, so the
+        // inverse of a staged edit should always fit.
+        while let Some(edit) = self.edits.pop() {
+            edit.inverse().apply(self.data)?;
+        }
+        Ok(())
+    }
+}
+
+fn header_row() -> Box<dyn std::error::Error> {
+    Box::new(errors::DataNotFound {
+        message: String::from("Record 0 is the header, only records after it can be inserted or removed"),
+    })
+}
+
+fn header_cell() -> Box<dyn std::error::Error> {
+    Box::new(errors::DataNotFound {
+        message: String::from("Record 0 is the header, use rename_column to change a column name"),
+    })
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        // Only reached with edits left when the transaction was neither
+        // committed nor rolled back, i.e. the closure panicked.
+        self.take_back().unwrap_or(());
+    }
+}
@@ -1,3 +1,5 @@
+use std::{borrow::Cow, fs, io::{BufRead, BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}, process, str, sync::atomic::{AtomicUsize, Ordering}};
+
 use super::{Record, errors, Result};
 
 /// In it's curernt form, this will remove all `\r` characters. as
@@ -25,9 +27,99 @@ pub fn raw_csv_to_records(raw: &str) -> Result<Vec<Record>> {
 }
 
 
+/// Same output as [`raw_csv_to_records`], but the parsing work is spread over
+/// `threads` scoped threads. The input is cut into chunks that always start on
+/// a record boundary, so quoted fields containing `\n` are never split.
+pub fn raw_csv_to_records_parallel(raw: &str, threads: usize) -> Result<Vec<Record>> {
+    if threads <= 1 {
+        return raw_csv_to_records(raw);
+    }
+    if !raw.contains(',') || !raw.contains('\n') {
+        return Err(
+            Box::new(errors::ParseError {
+                message: String::from("Unable to parse CSV, please check file"),
+            })
+        );
+    }
+
+    let chunks = split_at_record_boundaries(raw, threads);
+
+    let parsed: Vec<Vec<Record>> = std::thread::scope(|s| {
+        let handles: Vec<_> = chunks
+            .iter()
+            .map(|chunk| {
+                s.spawn(move || {
+                    parse_string_to_vec_ignore_quotes(chunk, '\n', false)
+                        .iter()
+                        .map(|line| parse_string_to_vec_ignore_quotes(line, ',', true))
+                        .collect::<Vec<Record>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("CSV parsing thread panicked"))
+            .collect()
+    });
+
+    Ok(parsed.into_iter().flatten().collect())
+}
+
+/// Cuts `raw` into at most `parts` slices. Every slice but the first starts
+/// right after a `\n` that sits outside of quotes, and the `\n` itself is
+/// dropped, so parsing the slices one after another is equivalent to parsing
+/// `raw` in one go.
+fn split_at_record_boundaries(raw: &str, parts: usize) -> Vec<&str> {
+    let bytes = raw.as_bytes();
+    let chunk_size = bytes.len().div_ceil(parts).max(1);
+
+    // Quote state at the start of each naive chunk, found by counting the
+    // quotes of every chunk in parallel and then taking the running parity.
+    let quote_counts: Vec<usize> = std::thread::scope(|s| {
+        let handles: Vec<_> = bytes
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn(move || chunk.iter().filter(|b| **b == b'"').count()))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("CSV quote counting thread panicked"))
+            .collect()
+    });
+
+    let mut cuts: Vec<usize> = Vec::new();
+    let mut in_quotes = false;
+    for (i, count) in quote_counts.iter().enumerate() {
+        let start = i * chunk_size;
+        if i > 0 && cuts.last().is_none_or(|last| start > *last) {
+            let mut quoted = in_quotes;
+            for (offset, b) in bytes[start..].iter().enumerate() {
+                if *b == b'"' {
+                    quoted = !quoted;
+                } else if *b == b'\n' && !quoted {
+                    cuts.push(start + offset);
+                    break;
+                }
+            }
+        }
+        in_quotes ^= count % 2 == 1;
+    }
+
+    let mut slices = Vec::with_capacity(cuts.len() + 1);
+    let mut begin = 0;
+    for cut in cuts {
+        slices.push(&raw[begin..cut]);
+        begin = cut + 1;
+    }
+    slices.push(&raw[begin..]);
+    slices
+}
+
+
 // TODO: add method to check for ',' in record fields, if so, 
 // surround that field with quotation marks
-pub fn records_to_string(records: &Vec<Record>, c: char) -> String {
+pub fn records_to_string(records: &[Record], c: char) -> String {
     let mut combined_records: Vec<String> = Vec::new();
     if c != ',' {
         let separtor_line = format!("sep={}\n", c);
@@ -54,12 +146,245 @@ pub fn records_to_string(records: &Vec<Record>, c: char) -> String {
 }
 
 
+/// Create a new temporary file next to `target`, e.g. `.data.csv.<pid>.<n>.tmp`.
+/// The counter keeps names unique between threads of this process and the
+/// file is only ever created, never opened if it already exists.
+pub fn create_temp_file(target: &Path) -> Result<(PathBuf, fs::File)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let file_name = target.file_name().and_then(|n| n.to_str()).unwrap_or("csv");
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp_path = dir.join(format!(".{}.{}.{}.tmp", file_name, process::id(), n));
+    let file = fs::OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+
+    Ok((temp_path, file))
+}
+
+
+/// The file a write to `path` should replace: a symlink is followed so the
+/// file it points to gets replaced rather than the link. Also returns the
+/// permissions of that file, so the replacement can keep them.
+fn resolve_target(path: &str) -> Result<(PathBuf, Option<fs::Permissions>)> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok((fs::canonicalize(path)?, Some(metadata.permissions()))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok((PathBuf::from(path), None)),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+
+/// Crash-safe replacement for `fs::write`. `contents` go to a temporary file in
+/// the same directory as `path`, which is synced and then renamed over `path`.
+/// An existing file keeps its permissions and a symlink keeps pointing to the
+/// file it did, which is the one replaced. With `backup` the previous file is
+/// kept as `<path>.bak`.
+pub fn write_atomic(path: &str, contents: &[u8], backup: bool) -> Result<()> {
+    let (target, permissions) = resolve_target(path)?;
+    let target = target.as_path();
+    let dir = match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let (temp_path, mut temp) = create_temp_file(target)?;
+
+    let written = (|| -> Result<()> {
+        if let Some(permissions) = permissions {
+            temp.set_permissions(permissions)?;
+        }
+        temp.write_all(contents)?;
+        temp.sync_all()?;
+
+        if backup && target.exists() {
+            let backup_path = format!("{}.bak", path);
+            fs::remove_file(&backup_path).unwrap_or(());
+            // A hard link keeps the old file without copying it, the rename
+            // below only swaps which file `path` points to.
+            if fs::hard_link(target, &backup_path).is_err() {
+                fs::copy(target, &backup_path)?;
+            }
+        }
+
+        fs::rename(&temp_path, target)?;
+        Ok(())
+    })();
+
+    if written.is_err() {
+        fs::remove_file(&temp_path).unwrap_or(());
+        return written;
+    }
+
+    // Make the rename itself durable. Not every platform can open directories,
+    // the data is already safe at this point so failures are ignored.
+    if let Ok(dir) = fs::File::open(dir) {
+        dir.sync_all().unwrap_or(());
+    }
+
+    Ok(())
+}
+
+
+/// Position of the column called `name` in the header (first record).
+pub fn column_index(data: &[Record], name: &str) -> Result<usize> {
+    data.first()
+        .and_then(|header| header.iter().position(|h| h == name))
+        .ok_or_else(|| -> Box<dyn std::error::Error> {
+            Box::new(errors::ColumnNotFound {
+                message: format!("No column named \"{}\" in the header", name),
+            })
+        })
+}
+
+
 pub fn path_validate(path: &str) {
     if !path.ends_with(".csv") {
         panic!("Path does not point to a CSV file. Please check your CSV instance...")
     }
 }
 
+/// Streams the start of a file until the first `\n` outside of quotes and
+/// parses it into a [`Record`]. Only the first record is read.
+pub fn read_first_record<R: Read>(reader: R) -> Result<Record> {
+    let mut line = Vec::new();
+    let mut in_quotes = false;
+
+    for b in BufReader::new(reader).bytes() {
+        let b = b?;
+        if b == b'"' {
+            in_quotes = !in_quotes;
+        } else if b == b'\n' && !in_quotes {
+            break;
+        }
+        line.push(b);
+    }
+
+    Ok(line_to_record(str::from_utf8(&line)?))
+}
+
+/// Reads records one at a time from any reader, so a file never has to be
+/// held in memory as a whole. Records are split the same way as
+/// [`raw_csv_to_records`], except that an empty last line (a file ending in
+/// `\n`) is not returned as a record.
+pub struct RecordReader<R: Read> {
+    reader: BufReader<R>,
+    line: Vec<u8>,
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(reader: R) -> RecordReader<R> {
+        RecordReader { reader: BufReader::with_capacity(1 << 16, reader), line: Vec::new() }
+    }
+
+    /// The next record, or `None` at the end of the input.
+    pub fn next_record(&mut self) -> Result<Option<Record>> {
+        self.line.clear();
+        let mut quotes = 0;
+
+        loop {
+            let start = self.line.len();
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                break;
+            }
+            quotes += self.line[start..].iter().filter(|b| **b == b'"').count();
+            if quotes % 2 == 0 {
+                break;
+            }
+        }
+
+        if self.line.is_empty() {
+            return Ok(None);
+        }
+        if self.line.last() == Some(&b'\n') {
+            self.line.pop();
+        }
+        Ok(Some(line_to_record(str::from_utf8(&self.line)?)))
+    }
+}
+
+/// Writes records one at a time to a temporary file next to `path`, which
+/// only replaces `path` once [`RecordWriter::finish`] is called, keeping its
+/// permissions and following a symlink the same as [`write_atomic`]. Dropping the
+/// writer before that removes the temporary file and leaves `path` alone.
+/// Records are written with [`record_to_quoted_line`] and, like
+/// [CSV::save](crate::CSV::save), without a `\n` after the last one.
+pub struct RecordWriter {
+    path: PathBuf,
+    temp_path: PathBuf,
+    writer: Option<BufWriter<fs::File>>,
+    written: usize,
+}
+
+impl RecordWriter {
+    pub fn new(path: &str) -> Result<RecordWriter> {
+        let (target, permissions) = resolve_target(path)?;
+        let (temp_path, file) = create_temp_file(&target)?;
+        if let Some(permissions) = permissions {
+            if let Err(e) = file.set_permissions(permissions) {
+                fs::remove_file(&temp_path).unwrap_or(());
+                return Err(Box::new(e));
+            }
+        }
+        let writer = BufWriter::new(file);
+
+        Ok(RecordWriter { path: target, temp_path, writer: Some(writer), written: 0 })
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<()> {
+        let writer = self.writer.as_mut().expect("RecordWriter used after finish");
+        if self.written > 0 {
+            writer.write_all(b"\n")?;
+        }
+        writer.write_all(record_to_quoted_line(record, ',').as_bytes())?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Sync the written records and move them to `path`. Returns the number
+    /// of records written.
+    pub fn finish(mut self) -> Result<usize> {
+        if let Some(writer) = self.writer.take() {
+            writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+        if let Err(e) = fs::rename(&self.temp_path, &self.path) {
+            fs::remove_file(&self.temp_path).unwrap_or(());
+            return Err(Box::new(e));
+        }
+        Ok(self.written)
+    }
+}
+
+impl Drop for RecordWriter {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            fs::remove_file(&self.temp_path).unwrap_or(());
+        }
+    }
+}
+
+/// Writes a record as a line, quoting fields that hold the delimiter `c`,
+/// a `\n` or a `\r` so they read back as a single field.
+pub fn record_to_quoted_line(record: &Record, c: char) -> String {
+    let fields: Vec<Cow<'_, str>> = record
+        .iter()
+        .map(|field| {
+            if field.contains([c, '\n', '\r']) {
+                Cow::Owned(format!("\"{}\"", field))
+            } else {
+                Cow::Borrowed(field.as_str())
+            }
+        })
+        .collect();
+    fields.join(&c.to_string())
+}
+
+/// Parse a single line (no unquoted `\n` inside) into a [`Record`].
+pub fn line_to_record(line: &str) -> Record {
+    parse_string_to_vec_ignore_quotes(line, ',', true)
+}
+
 /// Private function but important to know that this will remove `\r` characters.
 fn parse_string_to_vec_ignore_quotes(raw_string: &str, delimeter: char, result_as_record: bool) -> Vec<String> {
     let mut return_vec = Vec::new();
@@ -88,6 +413,60 @@ fn parse_string_to_vec_ignore_quotes(raw_string: &str, delimeter: char, result_a
     return_vec
 }
 
+/// Byte ranges of every record in `raw`, split the same way as
+/// [`raw_csv_to_records`] splits lines. The ranges exclude the `\n`. Each
+/// record is checked to be UTF8 as soon as its end is found, while it is
+/// still in cache, so `raw` is only read once.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub fn record_spans(raw: &[u8]) -> Result<Vec<(usize, usize)>> {
+    let mut spans = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+
+    for (i, b) in raw.iter().enumerate() {
+        if *b == b'"' {
+            in_quotes = !in_quotes;
+        } else if *b == b'\n' && !in_quotes {
+            str::from_utf8(&raw[start..i])?;
+            spans.push((start, i));
+            start = i + 1;
+        }
+    }
+    str::from_utf8(&raw[start..])?;
+    spans.push((start, raw.len()));
+
+    Ok(spans)
+}
+
+/// Borrowing version of splitting a single line into fields. Fields only get
+/// copied when quotes or `\r` characters have to be stripped from them.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub fn parse_fields_borrowed(line: &str, delimeter: char) -> Vec<Cow<'_, str>> {
+    let mut fields = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+
+    for (i, c) in line.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c == delimeter && !in_quotes {
+            fields.push(unquote_field(&line[start..i]));
+            start = i + c.len_utf8();
+        }
+    }
+    fields.push(unquote_field(&line[start..]));
+
+    fields
+}
+
+fn unquote_field(field: &str) -> Cow<'_, str> {
+    if field.contains(['"', '\r']) {
+        Cow::Owned(field.chars().filter(|c| *c != '"' && *c != '\r').collect())
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
 
 #[cfg(test)]
 mod utils_tests {
@@ -144,6 +523,78 @@ mod utils_tests {
         assert_eq!(expected, result);
     }
 
+    #[test]
+    fn utils_raw_csv_to_records_parallel_matches_sequential() {
+        let csv_string = String::from("\"one,one,one\",two,three\nfour,\"fi\nve\",six\n\"s,e,v\ne,n\",eight,nine\nten,\"\"\"eleven\"\"\",twelve\n");
+        let expected = raw_csv_to_records(&csv_string).expect("failed to parse sequentially");
+
+        for threads in 1..=16 {
+            let result = raw_csv_to_records_parallel(&csv_string, threads)
+                .expect("failed to parse in parallel");
+            assert_eq!(expected, result, "mismatch with {} threads", threads);
+        }
+    }
+
+    #[test]
+    fn record_boundaries_skip_quoted_newlines() {
+        let raw = "a,\"b\nb\nb\nb\",c\nd,e,f";
+        let slices = split_at_record_boundaries(raw, 4);
+        assert_eq!(vec!["a,\"b\nb\nb\nb\",c", "d,e,f"], slices);
+    }
+
+    #[test]
+    fn record_spans_and_borrowed_fields_match_records() {
+        let csv_string = String::from("\"one,one,one\",two,three\r\nfour,\"fi\nve\",six\n,\"s,e,v\ne,n\",,nine\n");
+        let expected = raw_csv_to_records(&csv_string).expect("failed to parse CSV");
+
+        let result: Vec<Record> = record_spans(csv_string.as_bytes())
+            .expect("failed to find records")
+            .into_iter()
+            .map(|(start, end)| {
+                parse_fields_borrowed(&csv_string[start..end], ',')
+                    .into_iter()
+                    .map(Cow::into_owned)
+                    .collect()
+            })
+            .collect();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn record_reader_streams_records() {
+        let csv_string = "\"one,one,one\",two,three\r\nfour,\"fi\nve\",six\n\"s,e,v\ne,n\",eight,nine\n";
+        let expected = raw_csv_to_records(csv_string.trim_end_matches('\n')).expect("failed to parse CSV");
+
+        let mut reader = RecordReader::new(csv_string.as_bytes());
+        let mut result: Vec<Record> = Vec::new();
+        while let Some(record) = reader.next_record().expect("failed to read record") {
+            result.push(record);
+        }
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn quoted_lines_read_back() {
+        let record: Record = vec![String::from("a,b"), String::from("new\nline"), String::from("plain")];
+        let line = record_to_quoted_line(&record, ',');
+        assert_eq!("\"a,b\",\"new\nline\",plain", line);
+        assert_eq!(vec![record], raw_csv_to_records(&line).unwrap_or_default());
+    }
+
+    #[test]
+    fn write_atomic_from_several_threads() {
+        let path = "./tests/test_data/write_atomic_threads.csv";
+        std::thread::scope(|s| {
+            for i in 0..8 {
+                s.spawn(move || write_atomic(path, format!("thread,{}", i).as_bytes(), false).expect("failed to write"));
+            }
+        });
+
+        let written = fs::read_to_string(path).expect("failed to read");
+        fs::remove_file(path).unwrap_or(());
+        assert!(written.starts_with("thread,"));
+    }
+
     #[test]
     fn create_lines_with_string_to_vec_ignore_quotes() {
         let expected: Vec<String> = vec![
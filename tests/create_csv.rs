@@ -1,3 +1,8 @@
+// create_edit_csv_file_from_instance matches on `&SaveState` patterns.
+#![allow(clippy::match_ref_pats)]
+
+use std::{fs, time::Duration};
+
 use csv::*;
 
 mod setup;
@@ -77,4 +82,498 @@ fn create_edit_csv_file_from_instance() {
     }
 
     setup::remove_extra_csvs();
-}
\ No newline at end of file
+}
+
+#[test]
+fn create_csv_instance_from_file_parallel() {
+    let csv = CSV::new_from_file("./tests/test_data/instance_with_quotes.csv")
+        .unwrap_or_else(|e| {
+            panic!("{:?}: Check the test_data folder for instance_with_quotes.csv!", e);
+        });
+
+    for threads in 0..8 {
+        let parallel_csv = CSV::new_from_file_parallel("./tests/test_data/instance_with_quotes.csv", threads)
+            .unwrap_or_else(|e| {
+                panic!("{:?}: parallel parse failed with {} threads!", e, threads);
+            });
+
+        assert_eq!(csv.data, parallel_csv.data);
+    }
+}
+
+#[test]
+fn read_records_with_index() {
+    let path = "./tests/test_data/index_test.csv";
+    setup::remove_test_files(&[path, &index_path(path)]);
+
+    let data: Vec<Record> = vec![
+        vec!["header 1".to_string(), "header 2".to_string()],
+        vec!["value 1".to_string(), "\"value, 2\nwith newline\"".to_string()],
+        vec!["value 3".to_string(), "value 4".to_string()],
+        vec!["value 5".to_string(), "value 6".to_string()],
+    ];
+    let mut csv = CSV::new_with_data(path, data);
+    if let Err(e) = csv.save() {
+        panic!("{:?}: save method failed!", e);
+    };
+
+    let expected = CSV::new_from_file(path)
+        .unwrap_or_else(|e| panic!("{:?}: save and read failed!", e))
+        .data;
+
+    let index = csv.build_index().unwrap_or_else(|e| panic!("{:?}: build_index failed!", e));
+    assert_eq!(csv.len(), index.len());
+    index.save(&index_path(path)).unwrap_or_else(|e| panic!("{:?}: index save failed!", e));
+
+    let mut reader = IndexedReader::open(path)
+        .unwrap_or_else(|e| panic!("{:?}: IndexedReader::open failed!", e));
+    assert_eq!(expected[2], reader.get(2).unwrap());
+    assert_eq!(expected[1..4].to_vec(), reader.range(1..4).unwrap());
+    assert_eq!("value, 2\nwith newline", reader.get(1).unwrap()[1]);
+    assert!(reader.get(4).is_err());
+    assert!(reader.get(usize::MAX).unwrap_err().is::<errors::DataNotFound>());
+
+    setup::remove_test_files(&[path, &index_path(path)]);
+}
+
+#[test]
+fn append_new_records_to_file() {
+    let path = "./tests/test_data/append_test.csv";
+    setup::remove_test_files(&[path]);
+
+    let mut csv = CSV::new_with_data(path, vec![
+        vec!["header 1".to_string(), "header 2".to_string()],
+        vec!["value 1".to_string(), "value 2".to_string()],
+    ]);
+    if let Err(e) = csv.append_new_records() {
+        panic!("{:?}: append to a missing file failed!", e);
+    };
+
+    {
+        let mut csv = CSV::new_from_file(path)
+            .unwrap_or_else(|e| panic!("{:?}: append and read failed!", e));
+        csv.insert_one(vec!["value 3".to_string(), "value 4".to_string()]);
+        if let Err(e) = csv.append_new_records() {
+            panic!("{:?}: append to an existing file failed!", e);
+        };
+    }
+
+    // A fresh instance with the same header only appends its new records.
+    let mut logger = CSV::new_with_data(path, vec![
+        vec!["header 1".to_string(), "header 2".to_string()],
+        vec!["value 5".to_string(), "value 6".to_string()],
+    ]);
+    if let Err(e) = logger.append_new_records() {
+        panic!("{:?}: append with a matching header failed!", e);
+    };
+
+    let expected: Vec<Record> = vec![
+        vec!["header 1".to_string(), "header 2".to_string()],
+        vec!["value 1".to_string(), "value 2".to_string()],
+        vec!["value 3".to_string(), "value 4".to_string()],
+        vec!["value 5".to_string(), "value 6".to_string()],
+    ];
+    let csv = CSV::new_from_file(path)
+        .unwrap_or_else(|e| panic!("{:?}: append and read failed!", e));
+    assert_eq!(expected, csv.data);
+
+    let mut mismatch = CSV::new_with_data(path, vec![
+        vec!["other 1".to_string(), "other 2".to_string()],
+    ]);
+    assert!(mismatch.append_new_records().is_err());
+
+    setup::remove_test_files(&[path]);
+}
+
+#[test]
+fn save_keeps_backup_of_previous_file() {
+    let path = "./tests/test_data/backup_test.csv";
+    let backup = "./tests/test_data/backup_test.csv.bak";
+    setup::remove_test_files(&[path, backup]);
+
+    let mut csv = CSV::new_with_data(path, vec![
+        vec!["header 1".to_string(), "header 2".to_string()],
+        vec!["value 1".to_string(), "value 2".to_string()],
+    ]);
+    csv.keep_backup(true);
+    if let Err(e) = csv.save() {
+        panic!("{:?}: save method failed!", e);
+    };
+    assert!(fs::metadata(backup).is_err());
+
+    csv.data[1][0] = "changed".to_string();
+    if let Err(e) = csv.save() {
+        panic!("{:?}: second save failed!", e);
+    };
+
+    let saved = CSV::new_from_file(path)
+        .unwrap_or_else(|e| panic!("{:?}: save and read failed!", e));
+    let previous = fs::read_to_string(backup)
+        .unwrap_or_else(|e| panic!("{:?}: backup was not written!", e));
+    assert_eq!("changed", saved.data[1][0]);
+    assert_eq!("header 1,header 2\nvalue 1,value 2", previous);
+
+    setup::remove_test_files(&[path, backup]);
+}
+
+#[test]
+fn tracked_changes_after_save_are_unsaved() {
+    let path = "./tests/test_data/tracking_test.csv";
+    setup::remove_test_files(&[path]);
+
+    let mut csv = CSV::new_with_data(path, vec![
+        vec!["header 1".to_string(), "header 2".to_string()],
+        vec!["value 1".to_string(), "value 2".to_string()],
+    ]);
+    if let Err(e) = csv.save() {
+        panic!("{:?}: save method failed!", e);
+    };
+    assert!(matches!(csv.check_state(), SaveState::Saved));
+
+    csv.insert_one(vec!["value 3".to_string(), "value 4".to_string()]);
+    assert!(matches!(csv.check_state(), SaveState::Unsaved));
+    assert_eq!(Some(vec![2]), csv.changed_rows());
+
+    // Appending writes the new record, nothing else is left unsaved.
+    if let Err(e) = csv.append_new_records() {
+        panic!("{:?}: append failed!", e);
+    };
+    assert!(matches!(csv.check_state(), SaveState::Saved));
+
+    // An edit to an existing record is not covered by appending.
+    csv.record_mut(1).unwrap()[0] = "changed".to_string();
+    csv.insert_one(vec!["value 5".to_string(), "value 6".to_string()]);
+    if let Err(e) = csv.append_new_records() {
+        panic!("{:?}: append failed!", e);
+    };
+    assert!(matches!(csv.check_state(), SaveState::Unsaved));
+    assert_eq!(Some(vec![1]), csv.changed_rows());
+
+    if let Err(e) = csv.save() {
+        panic!("{:?}: save method failed!", e);
+    };
+    assert!(csv.check_unsaved().is_ok());
+    assert_eq!(Some(vec![]), csv.changed_rows());
+
+    // Writing to `CSV.data` directly is noticed as well.
+    csv.data[1][1] = "direct".to_string();
+    assert!(matches!(csv.check_state(), SaveState::Unsaved));
+    assert_eq!(None, csv.changed_rows());
+    assert!(csv.check_unsaved().is_err());
+    csv.data[1][1] = "value 2".to_string();
+    assert!(csv.check_unsaved().is_ok());
+
+    csv.data.push(vec!["value 7".to_string(), "value 8".to_string()]);
+    assert!(csv.check_unsaved().is_err());
+    if let Err(e) = csv.append_new_records() {
+        panic!("{:?}: append failed!", e);
+    };
+    assert!(matches!(csv.check_state(), SaveState::Saved));
+    assert_eq!(csv.data, CSV::new_from_file(path).unwrap().data);
+
+    csv.data[2][0] = "direct".to_string();
+    csv.data.push(vec!["value 9".to_string(), "value 10".to_string()]);
+    if let Err(e) = csv.append_new_records() {
+        panic!("{:?}: append failed!", e);
+    };
+    assert!(matches!(csv.check_state(), SaveState::Unsaved));
+
+    setup::remove_test_files(&[path]);
+}
+
+#[test]
+fn save_refuses_external_changes() {
+    let path = "./tests/test_data/external_change_test.csv";
+    setup::remove_test_files(&[path]);
+    fs::write(path, "header 1,header 2\nvalue 1,value 2").unwrap();
+
+    let mut csv = CSV::new_from_file(path)
+        .unwrap_or_else(|e| panic!("{:?}: read failed!", e));
+
+    // Our own appends are not external changes.
+    csv.insert_one(vec!["value 3".to_string(), "value 4".to_string()]);
+    if let Err(e) = csv.append_new_records() {
+        panic!("{:?}: append failed!", e);
+    };
+    if let Err(e) = csv.save() {
+        panic!("{:?}: save after append failed!", e);
+    };
+
+    fs::write(path, "header 1,header 2\nsomeone,else").unwrap();
+    csv.insert_one(vec!["value 5".to_string(), "value 6".to_string()]);
+    assert!(csv.save().is_err());
+    assert!(csv.save_custom(';').is_err());
+    assert_eq!("header 1,header 2\nsomeone,else", fs::read_to_string(path).unwrap());
+
+    if let Err(e) = csv.reload() {
+        panic!("{:?}: reload failed!", e);
+    };
+    assert_eq!(vec!["someone".to_string(), "else".to_string()], csv.data[1]);
+    assert_eq!(2, csv.len());
+
+    fs::write(path, "header 1,header 2\nchanged,again").unwrap();
+    if let Err(e) = csv.force_save() {
+        panic!("{:?}: force_save failed!", e);
+    };
+    assert_eq!("header 1,header 2\nsomeone,else", fs::read_to_string(path).unwrap());
+
+    setup::remove_test_files(&[path]);
+}
+
+#[test]
+fn locked_csv_blocks_other_writers() {
+    let path = "./tests/test_data/lock_test.csv";
+    setup::remove_test_files(&[path, &lock_path(path)]);
+    fs::write(path, "header 1,header 2\nvalue 1,value 2").unwrap();
+
+    {
+        let mut writer = LockedCSV::open(path, LockMode::Exclusive)
+            .unwrap_or_else(|e| panic!("{:?}: exclusive lock failed!", e));
+
+        assert!(LockedCSV::try_open(path, LockMode::Shared).is_err());
+        assert!(LockedCSV::open_timeout(path, LockMode::Exclusive, Duration::from_millis(30)).is_err());
+
+        writer.transaction(|tx| tx.insert_one(vec!["value 3".to_string(), "value 4".to_string()])).unwrap();
+        if let Err(e) = writer.save() {
+            panic!("{:?}: save under exclusive lock failed!", e);
+        };
+    }
+
+    let mut reader = LockedCSV::try_open(path, LockMode::Shared)
+        .unwrap_or_else(|e| panic!("{:?}: shared lock failed!", e));
+    let other_reader = LockedCSV::try_open(path, LockMode::Shared)
+        .unwrap_or_else(|e| panic!("{:?}: second shared lock failed!", e));
+    assert_eq!(3, reader.len());
+    assert_eq!(3, other_reader.len());
+    reader.transaction(|tx| tx.insert_one(vec!["value 5".to_string(), "value 6".to_string()])).unwrap();
+    assert!(reader.save().is_err());
+    assert!(reader.append_new_records().is_err());
+    assert!(LockedCSV::try_open(path, LockMode::Exclusive).is_err());
+
+    let csv = reader.unlock().unwrap();
+    drop(other_reader);
+    assert_eq!(4, csv.len());
+    assert_eq!(3, CSV::new_from_file(path).unwrap().len());
+    assert!(LockedCSV::try_open(path, LockMode::Exclusive).is_ok());
+
+    setup::remove_test_files(&[path, &lock_path(path)]);
+}
+
+#[test]
+fn external_sort_matches_in_memory_sort() {
+    let input = "./tests/test_data/external_sort_input.csv";
+    let output = "./tests/test_data/external_sort_output.csv";
+    setup::remove_test_files(&[input, output]);
+
+    let mut lines = vec![String::from("id,group,note")];
+    for i in 0..500 {
+        lines.push(format!("{},{},\"note {}, with comma\"", i, (i * 7919) % 13, i));
+    }
+    fs::write(input, lines.join("\n")).unwrap();
+
+    let keys = [
+        SortKey::descending("group", Compare::Numeric),
+        SortKey::ascending("id", Compare::Natural),
+    ];
+
+    let mut expected = CSV::new_from_file(input)
+        .unwrap_or_else(|e| panic!("{:?}: read failed!", e));
+    expected.sort_by_columns(&keys).unwrap();
+
+    // A tiny budget forces many runs to be spilled and merged.
+    let runs = external_sort(input, output, &keys, 4096)
+        .unwrap_or_else(|e| panic!("{:?}: external sort failed!", e));
+    assert!(runs > 1);
+    let sorted = CSV::new_from_file(output)
+        .unwrap_or_else(|e| panic!("{:?}: read sorted file failed!", e));
+    assert_eq!(expected.data, sorted.data);
+
+    // A run per record needs more than one merge pass.
+    let runs = external_sort(input, output, &keys, 1)
+        .unwrap_or_else(|e| panic!("{:?}: external sort failed!", e));
+    assert_eq!(500, runs);
+    let sorted = CSV::new_from_file(output)
+        .unwrap_or_else(|e| panic!("{:?}: read sorted file failed!", e));
+    assert_eq!(expected.data, sorted.data);
+
+    // Two sorts to the same output at once keep their runs apart.
+    std::thread::scope(|s| {
+        for _ in 0..2 {
+            s.spawn(|| external_sort(input, output, &keys, 4096).unwrap_or_else(|e| panic!("{:?}: external sort failed!", e)));
+        }
+    });
+    let sorted = CSV::new_from_file(output)
+        .unwrap_or_else(|e| panic!("{:?}: read sorted file failed!", e));
+    assert_eq!(expected.data, sorted.data);
+
+    // Sorting in place with everything in a single run.
+    let runs = external_sort(input, input, &keys, usize::MAX)
+        .unwrap_or_else(|e| panic!("{:?}: external sort failed!", e));
+    assert_eq!(1, runs);
+    let sorted = CSV::new_from_file(input)
+        .unwrap_or_else(|e| panic!("{:?}: read sorted file failed!", e));
+    assert_eq!(expected.data, sorted.data);
+
+    assert!(external_sort(input, output, &[SortKey::ascending("missing", Compare::Lexical)], 4096).is_err());
+
+    setup::remove_test_files(&[input, output]);
+}
+
+#[test]
+fn join_files_matches_in_memory_join() {
+    let customers = "./tests/test_data/join_customers.csv";
+    let orders = "./tests/test_data/join_orders.csv";
+    let output = "./tests/test_data/join_output.csv";
+    setup::remove_test_files(&[customers, orders, output]);
+
+    fs::write(customers, "id,name\n1,ann\n2,bob\n7,dee\n3,\"cy, jr\"").unwrap();
+    let mut lines = vec![String::from("customer_id,name,total")];
+    for i in 0..50 {
+        lines.push(format!("{},item {},{}", i % 5, i, i * 3));
+    }
+    fs::write(orders, lines.join("\n")).unwrap();
+
+    let left = CSV::new_from_file(customers).unwrap();
+    let right = CSV::new_from_file(orders).unwrap();
+    let on = [("id", "customer_id")];
+    let sorted = |mut data: Vec<Record>| {
+        data[1..].sort();
+        data
+    };
+
+    for kind in [JoinKind::Inner, JoinKind::Left, JoinKind::Right, JoinKind::Full, JoinKind::Semi, JoinKind::Anti] {
+        let expected = left.join(&right, &on, kind)
+            .unwrap_or_else(|e| panic!("{:?}: join failed!", e));
+
+        let written = join_files(customers, orders, output, &on, kind, ("_left", "_right"))
+            .unwrap_or_else(|e| panic!("{:?}: join failed!", e));
+        assert_eq!(expected.len() - 1, written);
+        let joined = CSV::new_from_file(output)
+            .unwrap_or_else(|e| panic!("{:?}: read joined file failed!", e));
+        assert_eq!(sorted(expected.data.clone()), sorted(joined.data));
+    }
+
+    assert!(join_files(customers, orders, output, &[("id", "id")], JoinKind::Inner, ("", "")).is_err());
+
+    setup::remove_test_files(&[customers, orders, output]);
+}
+
+#[test]
+fn dedup_file_matches_in_memory_dedup() {
+    let input = "./tests/test_data/dedup_input.csv";
+    let output = "./tests/test_data/dedup_output.csv";
+    setup::remove_test_files(&[input, output]);
+
+    let mut lines = vec![String::from("id,group")];
+    for i in 0..200 {
+        lines.push(format!("{},{}", i % 70, i % 3));
+    }
+    fs::write(input, lines.join("\n")).unwrap();
+
+    for keep in [Keep::First, Keep::Last, Keep::None] {
+        for keys in [None, Some(&["group"][..])] {
+            let mut expected = CSV::new_from_file(input).unwrap();
+            let dropped = expected.dedup(keys, keep).unwrap();
+
+            assert_eq!(dropped, dedup_file(input, output, keys, keep)
+                .unwrap_or_else(|e| panic!("{:?}: dedup failed!", e)));
+            // Keep::None by group leaves only the header, which the reader
+            // can't parse on its own.
+            if expected.len() > 1 {
+                let deduped = CSV::new_from_file(output)
+                    .unwrap_or_else(|e| panic!("{:?}: read deduped file failed!", e));
+                assert_eq!(expected.data, deduped.data);
+            } else {
+                assert_eq!("id,group", fs::read_to_string(output).unwrap());
+            }
+        }
+    }
+
+    setup::remove_test_files(&[input, output]);
+}
+
+#[test]
+fn concat_files_matches_in_memory_concat() {
+    let first = "./tests/test_data/concat_first.csv";
+    let second = "./tests/test_data/concat_second.csv";
+    let output = "./tests/test_data/concat_output.csv";
+    setup::remove_test_files(&[first, second, output]);
+
+    fs::write(first, "id,total\n1,10\n2,\"1,5\"").unwrap();
+    fs::write(second, "total,id,note\n7,3,late\n8,4,").unwrap();
+
+    for policy in [HeaderPolicy::Union, HeaderPolicy::Intersection] {
+        let expected = CSV::concat(&[CSV::new_from_file(first).unwrap(), CSV::new_from_file(second).unwrap()], policy)
+            .unwrap_or_else(|e| panic!("{:?}: concat failed!", e));
+
+        assert_eq!(4, concat_files(&[first, second], output, policy)
+            .unwrap_or_else(|e| panic!("{:?}: concat failed!", e)));
+        let combined = CSV::new_from_file(output)
+            .unwrap_or_else(|e| panic!("{:?}: read combined file failed!", e));
+        assert_eq!(expected.data, combined.data);
+    }
+
+    assert!(concat_files(&[first, second], output, HeaderPolicy::Strict).is_err());
+
+    setup::remove_test_files(&[first, second, output]);
+}
+
+#[test]
+fn split_parts_written_to_directory() {
+    let dir = "./tests/test_data/split_parts";
+    fs::remove_dir_all(dir).unwrap_or(());
+
+    let csv = CSV::new_with_data("./tests/test_data/orders.csv", vec![
+        vec![String::from("customer"), String::from("total")],
+        vec![String::from("acme"), String::from("10")],
+        vec![String::from("zeta, inc"), String::from("200")],
+        vec![String::from("acme"), String::from("3")],
+    ]);
+
+    let parts = csv.partition_by("customer").unwrap();
+    let paths = write_parts(&parts, dir, "{stem}_{value}.csv")
+        .unwrap_or_else(|e| panic!("{:?}: write parts failed!", e));
+    assert_eq!(vec![
+        format!("{}/orders_acme.csv", dir),
+        format!("{}/orders_zeta__inc.csv", dir),
+    ], paths);
+    for (part, path) in parts.iter().zip(&paths) {
+        let written = CSV::new_from_file(path)
+            .unwrap_or_else(|e| panic!("{:?}: read part failed!", e));
+        assert_eq!(part.csv.data, written.data);
+    }
+
+    let parts = csv.split_by_rows(2).unwrap();
+    let paths = write_parts(&parts, dir, "{stem}_{n}.csv").unwrap();
+    assert_eq!(format!("{}/orders_2.csv", dir), paths[1]);
+    assert!(write_parts(&parts, dir, "{stem}.csv").is_err());
+
+    fs::remove_dir_all(dir).unwrap_or(());
+}
+
+#[cfg(unix)]
+#[test]
+fn save_keeps_permissions_and_symlinks() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    let path = "./tests/test_data/permissions_test.csv";
+    let link = "./tests/test_data/permissions_link.csv";
+    setup::remove_test_files(&[path, link]);
+
+    fs::write(path, "header 1,header 2\nvalue 1,value 2").unwrap();
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600)).unwrap();
+    symlink("permissions_test.csv", link).unwrap();
+
+    let mut csv = CSV::new_from_file(link)
+        .unwrap_or_else(|e| panic!("{:?}: read failed!", e));
+    csv.insert_one(vec!["value 3".to_string(), "value 4".to_string()]);
+    if let Err(e) = csv.save() {
+        panic!("{:?}: save method failed!", e);
+    };
+
+    assert!(fs::symlink_metadata(link).unwrap().file_type().is_symlink());
+    assert_eq!(0o600, fs::metadata(path).unwrap().permissions().mode() & 0o777);
+    assert_eq!(3, CSV::new_from_file(path).unwrap().len());
+
+    setup::remove_test_files(&[path, link]);
+}
@@ -2,4 +2,10 @@ use std::fs;
 
 pub fn remove_extra_csvs() {
     fs::remove_file("./tests/test_data/save_test.csv").unwrap_or(());
-}
\ No newline at end of file
+}
+
+pub fn remove_test_files(paths: &[&str]) {
+    for path in paths {
+        fs::remove_file(path).unwrap_or(());
+    }
+}